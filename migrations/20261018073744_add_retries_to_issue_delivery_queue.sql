@@ -0,0 +1,5 @@
+-- migrations/add_retries_to_issue_delivery_queue.sql
+ALTER TABLE issue_delivery_queue
+	ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE issue_delivery_queue
+	ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
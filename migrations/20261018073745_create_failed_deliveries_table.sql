@@ -0,0 +1,9 @@
+-- migrations/create_failed_deliveries_table.sql
+CREATE TABLE failed_deliveries (
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	subscriber_email TEXT NOT NULL,
+	n_retries SMALLINT NOT NULL,
+	failed_at timestamptz NOT NULL,
+	PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,3 @@
+-- migrations/add_enqueued_at_to_issue_delivery_queue.sql
+ALTER TABLE issue_delivery_queue
+	ADD COLUMN enqueued_at timestamptz NOT NULL DEFAULT now();
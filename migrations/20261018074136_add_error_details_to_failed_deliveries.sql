@@ -0,0 +1,10 @@
+-- migrations/add_error_details_to_failed_deliveries.sql
+BEGIN;
+	ALTER TABLE failed_deliveries ADD COLUMN error_chain TEXT NULL;
+	ALTER TABLE failed_deliveries ADD COLUMN enqueued_at timestamptz NULL;
+	UPDATE failed_deliveries
+		SET error_chain = '', enqueued_at = failed_at
+		WHERE error_chain IS NULL;
+	ALTER TABLE failed_deliveries ALTER COLUMN error_chain SET NOT NULL;
+	ALTER TABLE failed_deliveries ALTER COLUMN enqueued_at SET NOT NULL;
+COMMIT;
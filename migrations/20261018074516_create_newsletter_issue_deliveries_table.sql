@@ -0,0 +1,19 @@
+-- migrations/create_newsletter_issue_deliveries_table.sql
+CREATE TABLE newsletter_issue_deliveries (
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	subscriber_email TEXT NOT NULL,
+	status TEXT NOT NULL,
+	updated_at timestamptz NOT NULL,
+	PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
+
+-- Backfill the ledger for deliveries that are still in flight
+INSERT INTO newsletter_issue_deliveries (newsletter_issue_id, subscriber_email, status, updated_at)
+SELECT newsletter_issue_id, subscriber_email, 'pending', enqueued_at
+FROM issue_delivery_queue;
+
+INSERT INTO newsletter_issue_deliveries (newsletter_issue_id, subscriber_email, status, updated_at)
+SELECT newsletter_issue_id, subscriber_email, 'failed', failed_at
+FROM failed_deliveries
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,3 @@
+-- migrations/add_request_hash_to_idempotency.sql
+-- Left nullable: keys saved before this migration have no fingerprint.
+ALTER TABLE idempotency ADD COLUMN request_hash BYTEA NULL;
@@ -0,0 +1,4 @@
+-- Existing tokens get a fresh validity window starting now
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE subscription_tokens ADD COLUMN used_at timestamptz NULL;
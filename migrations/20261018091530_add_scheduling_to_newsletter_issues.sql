@@ -0,0 +1,5 @@
+-- migrations/add_scheduling_to_newsletter_issues.sql
+ALTER TABLE newsletter_issues
+	ADD COLUMN scheduled_for timestamptz NULL;
+ALTER TABLE newsletter_issues
+	ADD COLUMN cancelled_at timestamptz NULL;
@@ -0,0 +1,6 @@
+-- migrations/add_drafts_to_newsletter_issues.sql
+-- Issues that have not been published yet are drafts.
+ALTER TABLE newsletter_issues
+	ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues
+	ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
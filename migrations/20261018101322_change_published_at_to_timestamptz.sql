@@ -0,0 +1,4 @@
+-- migrations/change_published_at_to_timestamptz.sql
+-- Issues were stored with `now()` cast to text, which Postgres parses back.
+ALTER TABLE newsletter_issues
+	ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz;
@@ -0,0 +1,54 @@
+-- Mailing lists, each with its own members
+CREATE TABLE lists(
+    list_id uuid NOT NULL,
+    PRIMARY KEY (list_id),
+    name TEXT NOT NULL UNIQUE,
+    -- The list used when a form does not pick one
+    is_default BOOLEAN NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX lists_single_default ON lists (is_default) WHERE is_default;
+
+CREATE TABLE list_memberships(
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    -- 'pending_confirmation', 'confirmed' or 'unsubscribed'
+    status TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id, subscriber_id)
+);
+
+-- The lists an issue is sent to
+CREATE TABLE newsletter_issue_lists(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+
+-- Everything that existed before lists belongs to the default list
+INSERT INTO lists (list_id, name, is_default)
+VALUES (gen_random_uuid(), 'Newsletter', true);
+
+INSERT INTO list_memberships (list_id, subscriber_id, status, created_at)
+SELECT
+    l.list_id,
+    s.id,
+    CASE WHEN s.status IN ('pending_confirmation', 'confirmed')
+        THEN s.status
+        ELSE 'unsubscribed'
+    END,
+    s.subscribed_at
+FROM subscriptions s, lists l;
+
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT i.newsletter_issue_id, l.list_id
+FROM newsletter_issues i, lists l;
+
+-- Confirmation links confirm a membership of one list
+ALTER TABLE subscription_tokens ADD COLUMN list_id uuid REFERENCES lists (list_id);
+UPDATE subscription_tokens SET list_id = (SELECT list_id FROM lists);
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
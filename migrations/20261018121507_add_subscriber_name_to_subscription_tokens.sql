@@ -0,0 +1,2 @@
+-- The name given when subscribing again, applied once the token confirms it
+ALTER TABLE subscription_tokens ADD COLUMN subscriber_name TEXT NULL;
@@ -0,0 +1,2 @@
+-- The filter an issue was sent with; issues sent to a segment stay private
+ALTER TABLE newsletter_issues ADD COLUMN audience_filter TEXT NULL;
@@ -0,0 +1,2 @@
+-- The Markdown source of an issue, kept so that drafts can be edited in Markdown
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
@@ -1,12 +1,16 @@
 //! src/configuration.rs
 
-use crate::email_client::EmailClient;
+use crate::email_client::{
+    EmailClient, EmailTransport, FileTransport, PostmarkTransport, SmtpTransport, MAX_BATCH_SIZE,
+};
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
 
 use crate::domain::SubscriberEmail;
+use crate::rate_limiter::RateLimiter;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -14,32 +18,210 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub issue_delivery: IssueDeliverySettings,
+    pub idempotency: IdempotencySettings,
+    pub subscriptions: SubscriptionSettings,
+    pub webhooks: WebhookSettings,
+    /// Taken from `APP_ENVIRONMENT`.
+    pub environment: Environment,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
+    /// Receives the test copies of newsletter drafts.
+    pub admin_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file_directory: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub require_tls: bool,
 }
 
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
+    pub fn admin(&self) -> Result<SubscriberEmail, String> {
+        SubscriberEmail::parse(self.admin_email.clone())
+    }
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
     pub fn client(self) -> EmailClient {
         let sender_email = self.sender().expect("Invalid sender email address.");
         let timeout = self.timeout();
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        let transport: Box<dyn EmailTransport> = match self.transport {
+            EmailTransportKind::Postmark => Box::new(PostmarkTransport::new(
+                self.base_url,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The smtp transport requires `email_client.smtp` settings.");
+                let credentials = smtp.username.zip(smtp.password);
+                Box::new(
+                    SmtpTransport::new(
+                        &smtp.host,
+                        smtp.port,
+                        credentials,
+                        smtp.require_tls,
+                        timeout,
+                    )
+                    .expect("Invalid SMTP relay settings."),
+                )
+            }
+            EmailTransportKind::File => {
+                let directory = self
+                    .file_directory
+                    .expect("The file transport requires `email_client.file_directory`.");
+                Box::new(
+                    FileTransport::new(directory)
+                        .expect("Failed to create the directory for outgoing emails."),
+                )
+            }
+        };
+        EmailClient::new(sender_email, transport)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IssueDeliverySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i16,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub backoff_base_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub backoff_max_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub rate_limit_per_second: f64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub rate_limit_burst: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub n_workers: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub empty_queue_poll_interval_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub error_poll_interval_milliseconds: u64,
+}
+
+impl IssueDeliverySettings {
+    pub fn backoff_base(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.backoff_base_milliseconds)
+    }
+    pub fn backoff_max(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.backoff_max_milliseconds)
+    }
+    /// Fails unless there is at least one worker and a batch holds between one
+    /// message and as many as the email provider accepts in a single call.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(1..=MAX_BATCH_SIZE as i64).contains(&self.batch_size) {
+            anyhow::bail!(
+                "The issue delivery batch size must be between 1 and {}, got {}.",
+                MAX_BATCH_SIZE,
+                self.batch_size
+            );
+        }
+        if self.n_workers == 0 {
+            anyhow::bail!("At least one issue delivery worker is needed.");
+        }
+        Ok(())
+    }
+    pub fn rate_limiter(&self) -> Result<RateLimiter, anyhow::Error> {
+        RateLimiter::new(self.rate_limit_per_second, self.rate_limit_burst)
+            .map_err(anyhow::Error::msg)
+            .context("Invalid issue delivery rate limit settings.")
+    }
+    pub fn empty_queue_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.empty_queue_poll_interval_milliseconds)
+    }
+    pub fn error_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.error_poll_interval_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IdempotencySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub in_flight_timeout_milliseconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ttl_seconds)
+    }
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+    pub fn in_flight_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.in_flight_timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_ttl_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_token_ttl_seconds)
+    }
+}
+
+/// Basic auth credentials the email provider must use to call our webhooks.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// The password committed in `base.yaml`: anyone can read it.
+const DEVELOPMENT_WEBHOOK_PASSWORD: &str = "webhook-password-for-local-development";
+
+impl WebhookSettings {
+    /// Fails outside of local development unless the committed password has
+    /// been replaced, e.g. through `APP_WEBHOOKS__PASSWORD`.
+    pub fn validate(&self, environment: &Environment) -> Result<(), anyhow::Error> {
+        if !matches!(environment, Environment::Local)
+            && self.password.expose_secret() == DEVELOPMENT_WEBHOOK_PASSWORD
+        {
+            anyhow::bail!(
+                "The webhook password must be set through APP_WEBHOOKS__PASSWORD \
+                outside of local development."
+            );
+        }
+        Ok(())
     }
 }
 
@@ -86,6 +268,8 @@ pub struct ApplicationSettings {
     pub hmac_secret: Secret<String>,
 }
 
+#[derive(serde::Deserialize, Clone)]
+#[serde(try_from = "String")]
 pub enum Environment {
     Local,
     Production,
@@ -138,6 +322,7 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .prefix_separator("_")
                 .separator("__"),
         )
+        .set_override("environment", environment.as_str())?
         .build()?;
 
     settings.try_deserialize::<Settings>()
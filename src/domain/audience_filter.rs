@@ -0,0 +1,218 @@
+//! src/domain/audience_filter.rs
+
+use crate::domain::SubscriberTag;
+
+/// Selects the subscribers an issue goes to, e.g.
+/// `tag:beta and not subscribed_within:30d`.
+///
+/// The terms are `tag:<tag>` and `subscribed_within:<n>d`. They combine with
+/// `and`, `or`, `not` and parentheses; `not` binds tighter than `and`, which
+/// binds tighter than `or`.
+#[derive(Debug, PartialEq)]
+pub enum AudienceFilter {
+    Tag(SubscriberTag),
+    SubscribedWithinDays(i32),
+    Not(Box<AudienceFilter>),
+    And(Box<AudienceFilter>, Box<AudienceFilter>),
+    Or(Box<AudienceFilter>, Box<AudienceFilter>),
+}
+
+const MAX_LENGTH: usize = 500;
+const MAX_DAYS: i32 = 36_500;
+
+impl AudienceFilter {
+    /// Returns `None` for an empty filter, which matches every subscriber.
+    pub fn parse(input: &str) -> Result<Option<AudienceFilter>, String> {
+        if input.len() > MAX_LENGTH {
+            return Err(format!(
+                "The audience filter cannot be longer than {} characters.",
+                MAX_LENGTH
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input),
+            position: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(None);
+        }
+        let filter = parser.or()?;
+        match parser.next() {
+            None => Ok(Some(filter)),
+            Some(token) => Err(format!("Unexpected `{}` in the audience filter.", token)),
+        }
+    }
+}
+
+/// Splits on whitespace, with each parenthesis as a token of its own.
+fn tokenize(input: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    for (i, c) in input.char_indices() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if let Some(start) = word_start.take() {
+                tokens.push(&input[start..i]);
+            }
+            if c == '(' || c == ')' {
+                tokens.push(&input[i..i + 1]);
+            }
+        } else if word_start.is_none() {
+            word_start = Some(i);
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push(&input[start..]);
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.position) {
+            Some(token) if token.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<AudienceFilter, String> {
+        let mut filter = self.and()?;
+        while self.eat_keyword("or") {
+            filter = AudienceFilter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<AudienceFilter, String> {
+        let mut filter = self.not()?;
+        while self.eat_keyword("and") {
+            filter = AudienceFilter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<AudienceFilter, String> {
+        if self.eat_keyword("not") {
+            Ok(AudienceFilter::Not(Box::new(self.not()?)))
+        } else {
+            self.term()
+        }
+    }
+
+    fn term(&mut self) -> Result<AudienceFilter, String> {
+        match self.next() {
+            None => Err("The audience filter ends too early.".into()),
+            Some("(") => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(filter),
+                    _ => Err("A parenthesis is not closed in the audience filter.".into()),
+                }
+            }
+            Some(token) => parse_term(token),
+        }
+    }
+}
+
+fn parse_term(token: &str) -> Result<AudienceFilter, String> {
+    let unknown_term = || {
+        format!(
+            "`{}` is not a known audience filter term. \
+            Use `tag:<tag>` or `subscribed_within:<n>d`.",
+            token
+        )
+    };
+    let (key, value) = token.split_once(':').ok_or_else(unknown_term)?;
+    match key.to_ascii_lowercase().as_str() {
+        "tag" => Ok(AudienceFilter::Tag(SubscriberTag::parse(value.into())?)),
+        "subscribed_within" => value
+            .strip_suffix('d')
+            .and_then(|days| days.parse().ok())
+            .filter(|days| (1..=MAX_DAYS).contains(days))
+            .map(AudienceFilter::SubscribedWithinDays)
+            .ok_or_else(|| format!("`{}` is not a valid number of days, such as `30d`.", value)),
+        _ => Err(unknown_term()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AudienceFilter::{self, And, Not, Or, SubscribedWithinDays, Tag};
+    use crate::domain::SubscriberTag;
+    use claims::{assert_err, assert_ok_eq};
+
+    fn tag(tag: &str) -> Box<AudienceFilter> {
+        Box::new(Tag(SubscriberTag::parse(tag.into()).unwrap()))
+    }
+
+    #[test]
+    fn an_empty_filter_matches_everybody() {
+        assert_ok_eq!(AudienceFilter::parse("  "), None);
+    }
+
+    #[test]
+    fn a_single_term_is_parsed() {
+        assert_ok_eq!(AudienceFilter::parse("tag:Beta"), Some(*tag("beta")));
+        assert_ok_eq!(
+            AudienceFilter::parse("subscribed_within:30d"),
+            Some(SubscribedWithinDays(30))
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        assert_ok_eq!(
+            AudienceFilter::parse("tag:a or tag:b AND not tag:c"),
+            Some(Or(
+                tag("a"),
+                Box::new(And(tag("b"), Box::new(Not(tag("c")))))
+            ))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_terms() {
+        assert_ok_eq!(
+            AudienceFilter::parse("(tag:a or tag:b)and not(tag:c)"),
+            Some(And(
+                Box::new(Or(tag("a"), tag("b"))),
+                Box::new(Not(tag("c")))
+            ))
+        );
+    }
+
+    #[test]
+    fn unknown_terms_are_rejected() {
+        assert_err!(AudienceFilter::parse("email:a@example.com"));
+        assert_err!(AudienceFilter::parse("beta"));
+        assert_err!(AudienceFilter::parse("tag:beta testers"));
+    }
+
+    #[test]
+    fn invalid_numbers_of_days_are_rejected() {
+        assert_err!(AudienceFilter::parse("subscribed_within:30"));
+        assert_err!(AudienceFilter::parse("subscribed_within:0d"));
+        assert_err!(AudienceFilter::parse("subscribed_within:99999999999d"));
+    }
+
+    #[test]
+    fn unbalanced_expressions_are_rejected() {
+        assert_err!(AudienceFilter::parse("(tag:a"));
+        assert_err!(AudienceFilter::parse("tag:a)"));
+        assert_err!(AudienceFilter::parse("tag:a and"));
+        assert_err!(AudienceFilter::parse("not"));
+    }
+}
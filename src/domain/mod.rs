@@ -1,9 +1,15 @@
 //! src/domain/mod.rs
 
+mod audience_filter;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod unsubscribe_token;
 
+pub use audience_filter::AudienceFilter;
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use unsubscribe_token::{UnsubscribeToken, Unsubscription};
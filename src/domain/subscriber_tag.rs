@@ -0,0 +1,61 @@
+//! src/domain/subscriber_tag.rs
+
+/// A label attached to subscribers, such as `beta`. Tags are case-insensitive
+/// and stored in lowercase.
+#[derive(Debug, PartialEq)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    pub fn parse(input: String) -> Result<SubscriberTag, String> {
+        let tag = input.trim().to_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= 32
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!(
+                "{input} is not a valid tag. Tags are made of up to 32 letters, \
+                digits, dashes and underscores."
+            ))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTag;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn tags_are_trimmed_and_lowercased() {
+        assert_ok_eq!(
+            SubscriberTag::parse(" Early-Adopter_2 ".into()).map(|t| t.as_ref().to_string()),
+            "early-adopter_2".to_string()
+        );
+    }
+
+    #[test]
+    fn empty_tags_are_rejected() {
+        assert_err!(SubscriberTag::parse("  ".into()));
+    }
+
+    #[test]
+    fn tags_longer_than_32_characters_are_rejected() {
+        assert_err!(SubscriberTag::parse("a".repeat(33)));
+    }
+
+    #[test]
+    fn tags_with_spaces_or_punctuation_are_rejected() {
+        assert_err!(SubscriberTag::parse("beta testers".into()));
+        assert_err!(SubscriberTag::parse("beta'".into()));
+    }
+}
@@ -0,0 +1,145 @@
+//! src/domain/unsubscribe_token.rs
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A per-subscriber token, signed with the application HMAC secret, that
+/// lets the holder unsubscribe without logging in.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+/// What a valid token allows: leaving one list or, for tokens issued before
+/// there were several lists, leaving all of them.
+#[derive(Debug, PartialEq)]
+pub struct Unsubscription {
+    pub subscriber_id: Uuid,
+    pub list_id: Option<Uuid>,
+}
+
+impl UnsubscribeToken {
+    pub fn generate(
+        subscriber_id: Uuid,
+        list_id: Option<Uuid>,
+        hmac_secret: &Secret<String>,
+    ) -> Self {
+        let tag = mac(subscriber_id, list_id, hmac_secret)
+            .finalize()
+            .into_bytes();
+        let mut bytes = subscriber_id.as_bytes().to_vec();
+        if let Some(list_id) = list_id {
+            bytes.extend_from_slice(list_id.as_bytes());
+        }
+        bytes.extend_from_slice(&tag);
+        Self(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn verify(
+        token: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Result<Unsubscription, anyhow::Error> {
+        let bytes = URL_SAFE_NO_PAD.decode(token)?;
+        // The tag is as long as a SHA-256 digest; anything before it is the
+        // subscriber id, optionally followed by the list id.
+        let (ids, tag) = match bytes.len() {
+            48 | 64 => bytes.split_at(bytes.len() - 32),
+            _ => anyhow::bail!("The unsubscribe token does not have a valid length."),
+        };
+        let (subscriber_id, list_id) = ids.split_at(16);
+        let subscriber_id = Uuid::from_slice(subscriber_id)?;
+        let list_id = match list_id {
+            [] => None,
+            list_id => Some(Uuid::from_slice(list_id)?),
+        };
+        mac(subscriber_id, list_id, hmac_secret).verify_slice(tag)?;
+        Ok(Unsubscription {
+            subscriber_id,
+            list_id,
+        })
+    }
+
+    pub fn unsubscribe_url(&self, base_url: &str) -> String {
+        format!("{}/subscriptions/unsubscribe?token={}", base_url, self.0)
+    }
+}
+
+fn mac(subscriber_id: Uuid, list_id: Option<Uuid>, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    if let Some(list_id) = list_id {
+        mac.update(list_id.as_bytes());
+    }
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UnsubscribeToken, Unsubscription};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_verified() {
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, Some(list_id), &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret()),
+            Unsubscription {
+                subscriber_id,
+                list_id: Some(list_id)
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_without_a_list_is_verified() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, None, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret()),
+            Unsubscription {
+                subscriber_id,
+                list_id: None
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Some(Uuid::new_v4()), &secret());
+        let other_secret = Secret::new("another-secret".to_string());
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &other_secret));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Some(Uuid::new_v4()), &secret());
+        let mut tampered = token.as_ref().to_string();
+        let first = if tampered.starts_with('A') { "B" } else { "A" };
+        tampered.replace_range(0..1, first);
+        assert_err!(UnsubscribeToken::verify(&tampered, &secret()));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(UnsubscribeToken::verify("not-a-token", &secret()));
+        assert_err!(UnsubscribeToken::verify("", &secret()));
+    }
+}
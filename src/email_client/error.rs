@@ -0,0 +1,82 @@
+//! src/email_client/error.rs
+
+use std::time::Duration;
+
+/// Why an email could not be sent, classified by what the caller should do
+/// about it.
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    /// Worth retrying: timeouts, connection errors, outages on the provider side.
+    #[error("The email provider could not be reached or failed to process the email.")]
+    Transient(#[source] anyhow::Error),
+    #[error("The email provider is rate limiting us.")]
+    RateLimited { retry_after: Option<Duration> },
+    /// The address does not exist or has bounced before: it will never work.
+    #[error("The recipient cannot receive emails: {0}")]
+    InvalidRecipient(String),
+    #[error("The email provider rejected our credentials: {0}")]
+    Unauthorized(String),
+    /// Our account cannot send anything for now: out of credits, pending
+    /// approval, unconfirmed sender...
+    #[error("The email provider refused to send from our account: {0}")]
+    AccountRestricted(String),
+    /// The provider refused this very email, sending it again will not help.
+    #[error("The email provider rejected the email: {0}")]
+    Permanent(String),
+}
+
+impl SendEmailError {
+    /// Builds an error from the `ErrorCode` and `Message` fields of a Postmark
+    /// error body.
+    pub(super) fn from_postmark(error_code: i64, message: String) -> Self {
+        match error_code {
+            10 => Self::Unauthorized(message),
+            // Sender signature not found or not confirmed, not allowed to
+            // send, account pending approval
+            400 | 401 | 405 | 412 => {
+                Self::AccountRestricted(format!("{} (Postmark error code {})", message, error_code))
+            }
+            406 => Self::InvalidRecipient(message),
+            _ => Self::Permanent(format!("{} (Postmark error code {})", message, error_code)),
+        }
+    }
+
+    /// Whether the error applies to every email we could send rather than to
+    /// this one: retrying it individually is pointless until it is fixed.
+    pub fn is_account_level(&self) -> bool {
+        matches!(self, Self::Unauthorized(_) | Self::AccountRestricted(_))
+    }
+
+    /// Copies an error that applies to several emails at once, such as a
+    /// failed batch call.
+    pub(super) fn duplicate(&self) -> Self {
+        match self {
+            Self::Transient(e) => Self::Transient(anyhow::anyhow!("{:#}", e)),
+            Self::RateLimited { retry_after } => Self::RateLimited {
+                retry_after: *retry_after,
+            },
+            Self::InvalidRecipient(message) => Self::InvalidRecipient(message.clone()),
+            Self::Unauthorized(message) => Self::Unauthorized(message.clone()),
+            Self::AccountRestricted(message) => Self::AccountRestricted(message.clone()),
+            Self::Permanent(message) => Self::Permanent(message.clone()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Transient(e.into())
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SendEmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        let code = e.status().map(|code| code.to_string());
+        match code.as_deref() {
+            Some("535") => Self::Unauthorized(e.to_string()),
+            Some("550" | "551" | "553") => Self::InvalidRecipient(e.to_string()),
+            _ if e.is_permanent() => Self::Permanent(e.to_string()),
+            _ => Self::Transient(e.into()),
+        }
+    }
+}
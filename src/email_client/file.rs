@@ -0,0 +1,77 @@
+//! src/email_client/file.rs
+
+use crate::email_client::{Email, EmailTransport, SendEmailError};
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::Path;
+
+/// Writes every email to `<directory>/<uuid>.eml` instead of sending it, to
+/// inspect outgoing emails during development.
+#[derive(Debug)]
+pub struct FileTransport {
+    sink: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileTransport {
+    pub fn new(directory: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(directory.as_ref())?;
+        Ok(Self {
+            sink: AsyncFileTransport::new(directory),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let message = email
+            .to_mime_message()
+            .map_err(|e| SendEmailError::Permanent(format!("{:#}", e)))?;
+        self.sink
+            .send(message)
+            .await
+            .map_err(|e| SendEmailError::Transient(e.into()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, FileTransport};
+    use claims::assert_ok;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_to_the_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        let email_client =
+            EmailClient::new(sender, Box::new(FileTransport::new(&directory).unwrap()));
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient,
+                "Hello",
+                "<p>Hello!</p>",
+                "Hello!",
+                &[("List-Unsubscribe", "<https://example.com>")],
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains("To: recipient@example.com"));
+        assert!(contents.contains("Subject: Hello"));
+        assert!(contents.contains("List-Unsubscribe: <https://example.com>"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! src/email_client/mod.rs
+
+mod error;
+mod file;
+mod postmark;
+mod smtp;
+
+pub use error::SendEmailError;
+pub use file::FileTransport;
+pub use postmark::{PostmarkTransport, MAX_BATCH_SIZE};
+pub use smtp::SmtpTransport;
+
+use crate::domain::SubscriberEmail;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::MultiPart;
+
+/// A fully rendered email, ready to be handed over to a transport.
+#[derive(Debug)]
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+impl Email<'_> {
+    /// Renders the email as a multipart MIME message, for the transports that
+    /// speak RFC 5322 rather than a provider's API.
+    fn to_mime_message(&self) -> Result<lettre::Message, anyhow::Error> {
+        let mut message = lettre::Message::builder()
+            .from(self.from.as_ref().parse()?)
+            .to(self.to.as_ref().parse()?)
+            .subject(self.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                self.text_body.to_string(),
+                self.html_body.to_string(),
+            ))?;
+        for (name, value) in self.headers {
+            let name = HeaderName::new_from_ascii(name.to_string())?;
+            message
+                .headers_mut()
+                .insert_raw(HeaderValue::new(name, value.to_string()));
+        }
+        Ok(message)
+    }
+}
+
+/// A way of getting an email out of the door: an email provider's API, an
+/// SMTP relay, a local directory...
+#[async_trait::async_trait]
+pub trait EmailTransport: std::fmt::Debug + Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError>;
+
+    /// Sends several emails at once, returning one outcome per email in the
+    /// same order. Transports without a batch API send them one by one.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+}
+
+/// One message of a batch: the sender is filled in by the [`EmailClient`].
+#[derive(Debug)]
+pub struct BatchEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+#[derive(Debug)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Box<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: Box<dyn EmailTransport>) -> Self {
+        Self { sender, transport }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    #[tracing::instrument(
+        name = "Send Email"
+        skip(html_content, text_content, headers)
+    )]
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError> {
+        let email = Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+        self.transport.send(&email).await
+    }
+
+    #[tracing::instrument(name = "Send Email Batch", skip_all, fields(n_emails = emails.len()))]
+    pub async fn send_batch(&self, emails: &[BatchEmail<'_>]) -> Vec<Result<(), SendEmailError>> {
+        let emails: Vec<_> = emails
+            .iter()
+            .map(|email| Email {
+                from: &self.sender,
+                to: email.recipient,
+                subject: email.subject,
+                html_body: email.html_content,
+                text_body: email.text_content,
+                headers: email.headers,
+            })
+            .collect();
+        self.transport.send_batch(&emails).await
+    }
+}
@@ -0,0 +1,553 @@
+//! src/email_client/postmark.rs
+
+use crate::email_client::{Email, EmailTransport, SendEmailError};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+/// Postmark accepts at most 500 messages per `/email/batch` call.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Sends emails through Postmark's `/email` and `/email/batch` JSON APIs.
+#[derive(Debug)]
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    pub fn new(base_url: String, authorization_token: Secret<String>, timeout: Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+        }
+    }
+}
+
+impl PostmarkTransport {
+    async fn post<Body: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &Body,
+    ) -> Result<reqwest::Response, SendEmailError> {
+        let response = self
+            .http_client
+            .post(format!("{}/{}", self.base_url, path))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(body)
+            .send()
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs);
+            return Err(SendEmailError::RateLimited { retry_after });
+        }
+        let transient = response.error_for_status_ref().unwrap_err();
+        if status.is_server_error() {
+            return Err(transient.into());
+        }
+        let body = response.json::<PostmarkError>().await.ok();
+        Err(match (status, body) {
+            (StatusCode::UNAUTHORIZED, body) => SendEmailError::Unauthorized(
+                body.map(|b| b.message)
+                    .unwrap_or_else(|| transient.to_string()),
+            ),
+            (_, Some(body)) => SendEmailError::from_postmark(body.error_code, body.message),
+            (_, None) => SendEmailError::Permanent(transient.to_string()),
+        })
+    }
+
+    async fn send_chunk(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let request_body: Vec<_> = emails.iter().map(SendEmailRequest::from).collect();
+        let response = self.post("email/batch", &request_body).await?;
+        // Postmark accepted the call: from here on, any email we cannot find
+        // a result for may have gone out already and must not be sent again.
+        let unknown = |reason: String| {
+            emails
+                .iter()
+                .map(|_| {
+                    Err(SendEmailError::Permanent(format!(
+                        "{} The email may or may not have been sent.",
+                        reason
+                    )))
+                })
+                .collect()
+        };
+        let responses: Vec<PostmarkError> = match response.json().await {
+            Ok(responses) => responses,
+            Err(e) => {
+                return Ok(unknown(format!(
+                    "Failed to read Postmark's results for a batch it accepted: {}.",
+                    e
+                )))
+            }
+        };
+        if responses.len() != emails.len() {
+            return Ok(unknown(format!(
+                "Postmark answered a batch of {} emails with {} results.",
+                emails.len(),
+                responses.len()
+            )));
+        }
+        Ok(responses
+            .into_iter()
+            .map(|response| match response.error_code {
+                0 => Ok(()),
+                error_code => Err(SendEmailError::from_postmark(error_code, response.message)),
+            })
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        self.post("email", &SendEmailRequest::from(email)).await?;
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                // A failed call must not affect the outcome of the chunks
+                // that went through, so the error is copied to each email.
+                Err(e) => outcomes.extend(chunk.iter().map(|_| Err(e.duplicate()))),
+            }
+        }
+        outcomes
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+impl<'a> From<&'a Email<'a>> for SendEmailRequest<'a> {
+    fn from(email: &'a Email<'a>) -> Self {
+        Self {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email
+                .headers
+                .iter()
+                .map(|(name, value)| EmailHeader { name, value })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+/// The body of Postmark's error responses, also used for the per-email results
+/// of a batch call, where an `ErrorCode` of 0 means success.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkError {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{BatchEmail, EmailClient, PostmarkTransport, SendEmailError};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                dbg!(&body);
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..2).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(email(), Box::new(transport))
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_them_to_postmark() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{ "Name": "List-Unsubscribe", "Value": "<https://example.com>" }]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[("List-Unsubscribe", "<https://example.com>")],
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_email() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let (accepted, rejected) = (email(), email());
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 406, "Message": "Inactive recipient." },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let (subject, content) = (subject(), content());
+        let batch: Vec<_> = [&accepted, &rejected]
+            .into_iter()
+            .map(|recipient| BatchEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect();
+        let outcomes = email_client.send_batch(&batch).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        assert_ok!(&outcomes[0]);
+        assert!(matches!(
+            outcomes[1],
+            Err(SendEmailError::InvalidRecipient(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let (recipient, subject, content) = (email(), subject(), content());
+        let email = BatchEmail {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+            headers: &[],
+        };
+        let outcomes = email_client.send_batch(&[email]).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 1);
+        assert_err!(&outcomes[0]);
+    }
+
+    async fn send_batch_of_two(response: ResponseTemplate) -> Vec<Result<(), SendEmailError>> {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(path("/email/batch"))
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let (recipients, subject, content) = ([email(), email()], subject(), content());
+        let batch: Vec<_> = recipients
+            .iter()
+            .map(|recipient| BatchEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect();
+        email_client.send_batch(&batch).await
+    }
+
+    #[tokio::test]
+    async fn an_accepted_batch_with_unreadable_results_is_not_retried() {
+        let response = ResponseTemplate::new(200).set_body_string("not json");
+
+        let outcomes = send_batch_of_two(response).await;
+
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert!(matches!(outcome, Err(SendEmailError::Permanent(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn an_accepted_batch_with_missing_results_is_not_retried() {
+        let response = ResponseTemplate::new(200)
+            .set_body_json(serde_json::json!([{ "ErrorCode": 0, "Message": "OK" }]));
+
+        let outcomes = send_batch_of_two(response).await;
+
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert!(matches!(outcome, Err(SendEmailError::Permanent(_))));
+        }
+    }
+
+    async fn send_email_error(response: ResponseTemplate) -> SendEmailError {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn an_inactive_recipient_is_reported_as_an_invalid_recipient() {
+        let response = ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        }));
+
+        let error = send_email_error(response).await;
+
+        assert!(matches!(error, SendEmailError::InvalidRecipient(_)));
+    }
+
+    #[tokio::test]
+    async fn other_postmark_rejections_are_reported_as_permanent() {
+        let response = ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 300,
+            "Message": "Invalid email request."
+        }));
+
+        let error = send_email_error(response).await;
+
+        assert!(matches!(error, SendEmailError::Permanent(_)));
+    }
+
+    #[tokio::test]
+    async fn a_429_is_reported_as_rate_limited_with_its_retry_after() {
+        let response = ResponseTemplate::new(429).insert_header("Retry-After", "30");
+
+        let error = send_email_error(response).await;
+
+        assert!(matches!(
+            error,
+            SendEmailError::RateLimited {
+                retry_after: Some(retry_after)
+            } if retry_after == std::time::Duration::from_secs(30)
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_401_is_reported_as_unauthorized() {
+        let response = ResponseTemplate::new(401).set_body_json(serde_json::json!({
+            "ErrorCode": 10,
+            "Message": "The Server Token you provided in the X-Postmark-Server-Token request header was invalid."
+        }));
+
+        let error = send_email_error(response).await;
+
+        assert!(matches!(error, SendEmailError::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn an_account_that_is_not_allowed_to_send_is_reported_as_restricted() {
+        let response = ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 405,
+            "Message": "You have run out of credits."
+        }));
+
+        let error = send_email_error(response).await;
+
+        assert!(matches!(error, SendEmailError::AccountRestricted(_)));
+    }
+
+    #[tokio::test]
+    async fn a_500_is_reported_as_transient() {
+        let error = send_email_error(ResponseTemplate::new(500)).await;
+
+        assert!(matches!(error, SendEmailError::Transient(_)));
+    }
+}
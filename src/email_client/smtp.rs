@@ -0,0 +1,185 @@
+//! src/email_client/smtp.rs
+
+use crate::email_client::{Email, EmailTransport, SendEmailError};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+/// Sends emails through a plain SMTP relay.
+#[derive(Debug)]
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    /// Without `require_tls` the connection is left unencrypted, which is only
+    /// acceptable for a relay running on the same host or network.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, Secret<String>)>,
+        require_tls: bool,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let message = email
+            .to_mime_message()
+            .map_err(|e| SendEmailError::Permanent(format!("{:#}", e)))?;
+        self.mailer.send(message).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, SendEmailError, SmtpTransport};
+    use claims::{assert_err, assert_ok};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Starts an SMTP server on a random port that accepts everything but
+    /// answers `RCPT TO` commands with `rcpt_reply`.
+    async fn stub_smtp_server(rcpt_reply: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_smtp(stream, rcpt_reply));
+            }
+        });
+        port
+    }
+
+    async fn serve_smtp(stream: TcpStream, rcpt_reply: &'static str) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let _ = writer.write_all(b"220 localhost ESMTP\r\n").await;
+        let mut in_data = false;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let reply = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                "250 2.0.0 Queued"
+            } else {
+                match line.get(..4).unwrap_or(&line).to_ascii_uppercase().as_str() {
+                    "EHLO" | "HELO" => "250 localhost",
+                    "RCPT" => rcpt_reply,
+                    "DATA" => {
+                        in_data = true;
+                        "354 End data with <CR><LF>.<CR><LF>"
+                    }
+                    "QUIT" => "221 2.0.0 Bye",
+                    _ => "250 2.0.0 OK",
+                }
+            };
+            if writer
+                .write_all(format!("{}\r\n", reply).as_bytes())
+                .await
+                .is_err()
+                || reply.starts_with("221")
+            {
+                break;
+            }
+        }
+    }
+
+    async fn send_email(port: u16) -> Result<(), SendEmailError> {
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        let transport =
+            SmtpTransport::new("127.0.0.1", port, None, false, Duration::from_secs(2)).unwrap();
+        EmailClient::new(sender, Box::new(transport))
+            .send_email(&recipient, "Hello", "<p>Hello!</p>", "Hello!")
+            .await
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_relay_accepts_the_email() {
+        // Arrange
+        let port = stub_smtp_server("250 2.1.5 OK").await;
+
+        // Act
+        let outcome = send_email(port).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn an_unknown_mailbox_is_an_invalid_recipient() {
+        // Arrange
+        let port = stub_smtp_server("550 5.1.1 No such user").await;
+
+        // Act
+        let outcome = send_email(port).await;
+
+        // Assert
+        let e = assert_err!(outcome);
+        assert!(matches!(e, SendEmailError::InvalidRecipient(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn other_permanent_rejections_are_permanent() {
+        // Arrange
+        let port = stub_smtp_server("554 5.7.1 Message rejected").await;
+
+        // Act
+        let outcome = send_email(port).await;
+
+        // Assert
+        let e = assert_err!(outcome);
+        assert!(matches!(e, SendEmailError::Permanent(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn temporary_rejections_are_transient() {
+        // Arrange
+        let port = stub_smtp_server("451 4.3.0 Try again later").await;
+
+        // Act
+        let outcome = send_email(port).await;
+
+        // Assert
+        let e = assert_err!(outcome);
+        assert!(matches!(e, SendEmailError::Transient(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_relay_is_transient() {
+        // Arrange
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        // Act
+        let outcome = send_email(port).await;
+
+        // Assert
+        let e = assert_err!(outcome);
+        assert!(matches!(e, SendEmailError::Transient(_)), "{:?}", e);
+    }
+}
@@ -0,0 +1,45 @@
+//! src/idempotency/expiry.rs
+
+use super::persistence::expiry_cutoff;
+use crate::configuration::{IdempotencySettings, Settings};
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+use sqlx::PgPool;
+use std::time::Duration;
+
+#[tracing::instrument(skip(pool), err)]
+pub async fn delete_expired_keys(pool: &PgPool, ttl: Duration) -> Result<u64, anyhow::Error> {
+    let n_deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE created_at <= $1
+        "#,
+        expiry_cutoff(ttl)?,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted_rows)
+}
+
+async fn expiry_loop(
+    pool: PgPool,
+    settings: IdempotencySettings,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_requested() {
+        if let Ok(n_deleted_rows) = delete_expired_keys(&pool, settings.ttl()).await {
+            tracing::info!("Deleted {} expired idempotency keys", n_deleted_rows);
+        }
+        shutdown.sleep(settings.cleanup_interval()).await;
+    }
+    Ok(())
+}
+
+pub async fn run_expiry_worker_until_stopped(
+    configuration: Settings,
+    shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    expiry_loop(connection_pool, configuration.idempotency, shutdown).await
+}
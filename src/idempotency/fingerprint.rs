@@ -0,0 +1,51 @@
+//! src/idempotency/fingerprint.rs
+
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestFingerprint(Vec<u8>);
+
+impl RequestFingerprint {
+    pub fn from_parts(parts: &[&str]) -> Self {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            // Length-prefix every part so that ("ab", "c") and ("a", "bc") differ.
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part.as_bytes());
+        }
+        Self(hasher.finalize().to_vec())
+    }
+}
+
+impl From<Vec<u8>> for RequestFingerprint {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl AsRef<[u8]> for RequestFingerprint {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestFingerprint;
+
+    #[test]
+    fn identical_parts_have_the_same_fingerprint() {
+        assert_eq!(
+            RequestFingerprint::from_parts(&["title", "body"]),
+            RequestFingerprint::from_parts(&["title", "body"])
+        );
+    }
+
+    #[test]
+    fn moving_a_boundary_between_parts_changes_the_fingerprint() {
+        assert_ne!(
+            RequestFingerprint::from_parts(&["ab", "c"]),
+            RequestFingerprint::from_parts(&["a", "bc"])
+        );
+    }
+}
@@ -1,7 +1,11 @@
 //! src/idempotency/mod.rs
 
+mod expiry;
+mod fingerprint;
 mod key;
 mod persistence;
 
+pub use expiry::{delete_expired_keys, run_expiry_worker_until_stopped};
+pub use fingerprint::RequestFingerprint;
 pub use key::IdempotencyKey;
 pub use persistence::{get_saved_response, save_response, try_processing, NextAction};
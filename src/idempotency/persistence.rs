@@ -1,9 +1,12 @@
 //! src/idempotency/persistence.rs
 
-use super::IdempotencyKey;
+use super::{IdempotencyKey, RequestFingerprint};
+use crate::configuration::IdempotencySettings;
 use actix_web::{body::to_bytes, HttpResponse};
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
 use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 #[derive(Debug, sqlx::Type)]
@@ -19,10 +22,17 @@ impl PgHasArrayType for HeaderPairRecord {
     }
 }
 
+/// Keys created before the returned instant are expired and must be treated
+/// as if they had never been seen.
+pub(super) fn expiry_cutoff(ttl: Duration) -> Result<DateTime<Utc>, anyhow::Error> {
+    Ok(Utc::now() - chrono::Duration::from_std(ttl)?)
+}
+
 pub async fn get_saved_response(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
     user_id: Uuid,
+    ttl: Duration,
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
     let saved_response = sqlx::query!(
         r#"
@@ -33,9 +43,12 @@ pub async fn get_saved_response(
         FROM idempotency
         WHERE
             user_id = $1 AND idempotency_key = $2
+            AND created_at > $3
+            AND response_status_code IS NOT NULL
         "#,
         user_id,
         idempotency_key.as_ref(),
+        expiry_cutoff(ttl)?,
     )
     .fetch_optional(pool)
     .await?;
@@ -98,37 +111,133 @@ pub async fn save_response(
 pub enum NextAction {
     StartProcessing(Transaction<'static, Postgres>),
     ReturnSavedResponse(HttpResponse),
+    // Another request with the same key is still being processed and did not
+    // complete within the in-flight timeout.
+    RequestInProgress,
+    // The key has already been used for a request with a different payload.
+    RejectMismatchedRequest,
 }
 
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
     user_id: Uuid,
+    request_fingerprint: &RequestFingerprint,
+    settings: &IdempotencySettings,
 ) -> Result<NextAction, anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let n_inserted_rows = sqlx::query!(
+    // A concurrent request holding the same key blocks our INSERT until it
+    // commits: bound that wait instead of queueing up indefinitely.
+    let previous_lock_timeout = set_lock_timeout(
+        &mut transaction,
+        &format!("{}ms", settings.in_flight_timeout_milliseconds),
+    )
+    .await?;
+    // An expired key is recycled as if it was brand new.
+    let outcome = sqlx::query!(
         r#"
         INSERT INTO idempotency (
             user_id,
             idempotency_key,
+            request_hash,
             created_at
         )
-        VALUES ($1, $2, now())
-        ON CONFLICT DO NOTHING 
+        VALUES ($1, $2, $4, now())
+        ON CONFLICT (user_id, idempotency_key) DO UPDATE
+        SET
+            created_at = now(),
+            request_hash = EXCLUDED.request_hash,
+            response_status_code = NULL,
+            response_headers = NULL,
+            response_body = NULL
+        WHERE idempotency.created_at <= $3
         "#,
         user_id,
         idempotency_key.as_ref(),
+        expiry_cutoff(settings.ttl())?,
+        request_fingerprint.as_ref(),
     )
     .execute(&mut transaction)
-    .await?
-    .rows_affected();
+    .await;
+    let n_inserted_rows = match outcome {
+        Ok(outcome) => outcome.rows_affected(),
+        Err(e) if is_lock_timeout(&e) => return Ok(NextAction::RequestInProgress),
+        Err(e) => return Err(e.into()),
+    };
 
     if n_inserted_rows > 0 {
+        set_lock_timeout(&mut transaction, &previous_lock_timeout).await?;
         Ok(NextAction::StartProcessing(transaction))
     } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
-        Ok(NextAction::ReturnSavedResponse(saved_response))
+        transaction.rollback().await?;
+        let stored_fingerprint = get_request_fingerprint(pool, idempotency_key, user_id).await?;
+        if stored_fingerprint.is_some_and(|f| &f != request_fingerprint) {
+            return Ok(NextAction::RejectMismatchedRequest);
+        }
+        wait_for_saved_response(pool, idempotency_key, user_id, settings).await
     }
 }
+
+async fn get_request_fingerprint(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<RequestFingerprint>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT request_hash
+        FROM idempotency
+        WHERE
+            user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row
+        .and_then(|r| r.request_hash)
+        .map(RequestFingerprint::from))
+}
+
+/// Poll for the response of a request that has already claimed the key,
+/// giving up once the in-flight timeout has elapsed.
+async fn wait_for_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    settings: &IdempotencySettings,
+) -> Result<NextAction, anyhow::Error> {
+    let deadline = Instant::now() + settings.in_flight_timeout();
+    loop {
+        if let Some(saved_response) =
+            get_saved_response(pool, idempotency_key, user_id, settings.ttl()).await?
+        {
+            return Ok(NextAction::ReturnSavedResponse(saved_response));
+        }
+        if Instant::now() >= deadline {
+            return Ok(NextAction::RequestInProgress);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Set `lock_timeout` for the rest of the transaction, returning its previous value.
+async fn set_lock_timeout(
+    transaction: &mut Transaction<'static, Postgres>,
+    lock_timeout: &str,
+) -> Result<String, sqlx::Error> {
+    let previous = sqlx::query!(r#"SELECT current_setting('lock_timeout') as "lock_timeout!""#)
+        .fetch_one(&mut *transaction)
+        .await?
+        .lock_timeout;
+    sqlx::query!("SELECT set_config('lock_timeout', $1, true)", lock_timeout)
+        .fetch_one(&mut *transaction)
+        .await?;
+    Ok(previous)
+}
+
+fn is_lock_timeout(e: &sqlx::Error) -> bool {
+    const LOCK_NOT_AVAILABLE: &str = "55P03";
+    matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some(LOCK_NOT_AVAILABLE))
+}
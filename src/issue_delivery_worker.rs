@@ -1,109 +1,381 @@
 //! src/issue_delivery_worker.rs
 
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
-    startup::get_connection_pool,
+    configuration::{IssueDeliverySettings, Settings},
+    domain::{SubscriberEmail, UnsubscribeToken},
+    email_client::{BatchEmail, EmailClient, SendEmailError},
+    rate_limiter::RateLimiter,
+    shutdown::ShutdownSignal,
+    startup::{get_connection_pool, ApplicationBaseUrl, HmacSecret},
+    template::{Recipient, Template},
 };
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::postgres::PgListener;
 use sqlx::{PgPool, Postgres, Transaction};
-use tracing::{field::display, Span};
+use tokio::sync::Notify;
+use tracing::Span;
 use uuid::Uuid;
 
+/// Publishing an issue notifies this Postgres channel, with the id of the
+/// issue as payload.
+pub const NEW_DELIVERIES_CHANNEL: &str = "new_deliveries";
+
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
 }
 
-#[tracing::instrument(
-    skip_all,
-    fields(
-        newsletter_issue_id=tracing::field::Empty,
-        subscriber_email=tracing::field::Empty
-    ),
-    err
-)]
+pub enum DeliveryStatus {
+    Pending,
+    Sent,
+    Failed,
+    Skipped,
+    Cancelled,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+            DeliveryStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Waits until the rate limit allows a full batch, then executes it with
+/// [`execute_batch`].
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    rate_limiter: &RateLimiter,
+    settings: &IssueDeliverySettings,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    rate_limiter.acquire(batch_tokens(settings)).await;
+    execute_batch(
+        pool,
+        email_client,
+        rate_limiter,
+        settings,
+        base_url,
+        hmac_secret,
+    )
+    .await
+}
+
+/// The tokens to acquire before dequeuing a batch.
+fn batch_tokens(settings: &IssueDeliverySettings) -> u32 {
+    u32::try_from(settings.batch_size).unwrap_or(0)
+}
+
+/// Dequeues up to `batch_size` due tasks, sends them as one batch and settles
+/// every task individually according to its own outcome.
+///
+/// The tokens for a full batch must have been acquired beforehand, so that
+/// waiting for the rate limit never holds the locks on the dequeued tasks:
+/// the tokens that are not needed are given back.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+async fn execute_batch(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &RateLimiter,
+    settings: &IssueDeliverySettings,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
-    if task.is_none() {
+    let (mut transaction, tasks) = match dequeue_tasks(pool, settings.batch_size).await {
+        Ok(dequeued) => dequeued,
+        Err(e) => {
+            rate_limiter.release(batch_tokens(settings)).await;
+            return Err(e);
+        }
+    };
+    if tasks.is_empty() {
+        rate_limiter.release(batch_tokens(settings)).await;
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) =
-        task.expect("This shouldn't be None because we already checked for None");
-    Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
-                .await
-            {
+    Span::current().record("n_tasks", tasks.len());
+    let mut issues = HashMap::new();
+    let mut prepared = Vec::with_capacity(tasks.len());
+    for task in &tasks {
+        let (subscriber_id, list_id) = match (
+            task.subscriber_id,
+            task.subscriber_status.as_deref(),
+            task.list_id,
+        ) {
+            (Some(subscriber_id), Some("confirmed"), Some(list_id)) => (subscriber_id, list_id),
+            _ => {
+                tracing::info!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a subscriber who is no longer confirmed on any target list"
+                );
+                delete_task(&mut transaction, task, DeliveryStatus::Skipped).await?;
+                continue;
+            }
+        };
+        let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => email,
+            Err(e) => {
                 tracing::error!(
                     error.cause_chain = ?e,
                     error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber. \
-                    Skipping.",
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Thier stored contact details are invalid",
                 );
+                dead_letter_task(&mut transaction, task, &anyhow::anyhow!(e)).await?;
+                continue;
             }
+        };
+        if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+            entry.insert(get_issue(pool, task.newsletter_issue_id).await?);
         }
-        Err(e) => {
-            tracing::error!(
-                error.cause_chain = ?e,
-                error.message = %e,
-                "Skipping a confirmed subscriber. \
-                Thier stored contact details are invalid",
-            )
+        let issue = &issues[&task.newsletter_issue_id];
+        let unsubscribe_url =
+            UnsubscribeToken::generate(subscriber_id, Some(list_id), &hmac_secret.0)
+                .unsubscribe_url(&base_url.0);
+        let recipient = Recipient {
+            name: task.subscriber_name.as_deref().unwrap_or_default(),
+            unsubscribe_url: &unsubscribe_url,
+            subscribed_at: task.subscribed_at.unwrap_or(task.enqueued_at),
+        };
+        let (html_content, text_content) =
+            render_issue(&issue.html_template, &issue.text_template, &recipient);
+        prepared.push(PreparedEmail {
+            task,
+            recipient: email,
+            html_content,
+            text_content,
+            list_unsubscribe: format!("<{}>", unsubscribe_url),
+        });
+    }
+    let headers: Vec<_> = prepared
+        .iter()
+        .map(|email| {
+            [
+                ("List-Unsubscribe", email.list_unsubscribe.as_str()),
+                ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+            ]
+        })
+        .collect();
+    let batch: Vec<_> = prepared
+        .iter()
+        .zip(&headers)
+        .map(|(email, headers)| BatchEmail {
+            recipient: &email.recipient,
+            subject: &issues[&email.task.newsletter_issue_id].title,
+            html_content: &email.html_content,
+            text_content: &email.text_content,
+            headers,
+        })
+        .collect();
+    // Skipped and dead-lettered tasks are not sent
+    rate_limiter
+        .release(batch_tokens(settings).saturating_sub(batch.len() as u32))
+        .await;
+    let outcomes = email_client.send_batch(&batch).await;
+    let retry_after = outcomes.iter().find_map(|outcome| match outcome {
+        Err(SendEmailError::RateLimited { retry_after }) => Some(*retry_after),
+        _ => None,
+    });
+    if let Some(retry_after) = retry_after {
+        let pause = retry_after.unwrap_or_else(|| settings.backoff_base());
+        tracing::warn!(pause = ?pause, "The email provider is rate limiting us. Pausing deliveries.");
+        rate_limiter.pause(pause).await;
+    }
+    let mut account_error = None;
+    for (email, outcome) in prepared.iter().zip(outcomes) {
+        let task = email.task;
+        match outcome {
+            Ok(()) => delete_task(&mut transaction, task, DeliveryStatus::Sent).await?,
+            // Not the fault of any delivery: the task stays as it is, its
+            // retries untouched, until the account is fixed.
+            Err(e) if e.is_account_level() => {
+                account_error.get_or_insert(e);
+            }
+            Err(e @ SendEmailError::InvalidRecipient(_)) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Marking a subscriber as bounced. \
+                    Their address cannot receive emails.",
+                );
+                mark_subscriber_as_bounced(&mut transaction, task).await?;
+                dead_letter_task(&mut transaction, task, &e.into()).await?;
+            }
+            Err(e @ SendEmailError::Permanent(_)) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Failed to deliver issue to a confirmed subscriber. \
+                    The email was rejected, giving up without retrying.",
+                );
+                dead_letter_task(&mut transaction, task, &e.into()).await?;
+            }
+            // Being throttled says nothing about the delivery itself: it is
+            // postponed without using up one of its retries.
+            Err(SendEmailError::RateLimited { retry_after }) => {
+                let delay = retry_after.unwrap_or_else(|| settings.backoff_base());
+                tracing::debug!(
+                    delay = ?delay,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Postponing a delivery while the email provider is rate limiting us."
+                );
+                postpone_task(&mut transaction, task, delay).await?;
+            }
+            Err(e) if task.n_retries >= settings.max_retries => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Failed to deliver issue to a confirmed subscriber. \
+                    Giving up after {} retries.",
+                    task.n_retries
+                );
+                dead_letter_task(&mut transaction, task, &e.into()).await?;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Failed to deliver issue to a confirmed subscriber. \
+                    Scheduling a retry.",
+                );
+                let delay = retry_backoff(settings, task.n_retries);
+                retry_task(&mut transaction, task, delay).await?;
+            }
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
+    transaction.commit().await?;
+    if let Some(e) = account_error {
+        return Err(anyhow::Error::new(e)
+            .context("Cannot deliver issues until the email provider accepts our account again."));
+    }
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+struct PreparedEmail<'a> {
+    task: &'a DeliveryTask,
+    recipient: SubscriberEmail,
+    html_content: String,
+    text_content: String,
+    list_unsubscribe: String,
+}
+
+/// Exponential backoff, capped at `backoff_max`, with "equal jitter":
+/// half of the delay is fixed and the other half is drawn at random so that
+/// tasks failing together do not all come back at the same instant.
+fn retry_backoff(settings: &IssueDeliverySettings, n_retries: i16) -> Duration {
+    let exponent = u32::try_from(n_retries).unwrap_or(0);
+    let delay = settings
+        .backoff_base()
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(settings.backoff_max());
+    let half = delay / 2;
+    let jitter = rand::thread_rng().gen_range(Duration::ZERO..=half);
+    half + jitter
+}
+
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    enqueued_at: DateTime<Utc>,
+    subscriber_id: Option<Uuid>,
+    subscriber_status: Option<String>,
+    subscriber_name: Option<String>,
+    subscribed_at: Option<DateTime<Utc>>,
+    /// The target list of the issue the subscriber is still a member of.
+    list_id: Option<Uuid>,
+}
+
 #[tracing::instrument(skip_all)]
-async fn dequeue_task(
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    batch_size: i64,
+) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
         r#"
-        SELECT newsletter_issue_id,  subscriber_email
-        FROM issue_delivery_queue
-        FOR UPDATE
+        SELECT
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            q.enqueued_at,
+            s.id as "subscriber_id?",
+            s.status as "subscriber_status?",
+            s.name as "subscriber_name?",
+            s.subscribed_at as "subscribed_at?",
+            (
+                SELECT m.list_id
+                FROM list_memberships m
+                JOIN newsletter_issue_lists il ON il.list_id = m.list_id
+                WHERE
+                    il.newsletter_issue_id = q.newsletter_issue_id
+                AND m.subscriber_id = s.id
+                AND m.status = 'confirmed'
+                ORDER BY m.created_at
+                LIMIT 1
+            ) as "list_id?"
+        FROM issue_delivery_queue q
+        LEFT JOIN subscriptions s ON s.email = q.subscriber_email
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
         SKIP LOCKED
-        LIMIT 1 
-        "#
+        LIMIT $1
+        "#,
+        batch_size
     )
-    .fetch_optional(&mut transaction)
+    .fetch_all(&mut transaction)
     .await?;
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok((transaction, tasks))
 }
 
 #[tracing::instrument(skip_all)]
 async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    status: DeliveryStatus,
 ) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_deliveries
+        SET
+            status = $3,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1
+        AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status.as_str()
+    )
+    .execute(&mut *transaction)
+    .await?;
     sqlx::query!(
         r#"
         DELETE FROM issue_delivery_queue
@@ -111,25 +383,137 @@ async fn delete_task(
             newsletter_issue_id = $1
         AND subscriber_email = $2
         "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email
     )
-    .execute(&mut transaction)
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3
+        WHERE
+            newsletter_issue_id = $1
+        AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = $3
+        WHERE
+            newsletter_issue_id = $1
+        AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO failed_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            error_chain,
+            enqueued_at,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries,
+        format!("{:#}", error),
+        task.enqueued_at
+    )
+    .execute(&mut *transaction)
+    .await?;
+    delete_task(transaction, task, DeliveryStatus::Failed).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_subscriber_as_bounced(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'bounced' WHERE email = $1"#,
+        task.subscriber_email
+    )
+    .execute(&mut *transaction)
     .await?;
-    transaction.commit().await?;
     Ok(())
 }
 
+/// Returns the HTML and text bodies of an issue as `recipient` receives them,
+/// with the unsubscribe link appended.
+pub fn render_issue(
+    html_template: &Template,
+    text_template: &Template,
+    recipient: &Recipient,
+) -> (String, String) {
+    (
+        format!(
+            "{}<p><a href=\"{}\">Unsubscribe</a></p>",
+            html_template.render_html(recipient),
+            recipient.unsubscribe_url
+        ),
+        format!(
+            "{}\n\nUnsubscribe: {}",
+            text_template.render_text(recipient),
+            recipient.unsubscribe_url
+        ),
+    )
+}
+
 struct NewsletterIssue {
     title: String,
-    text_content: String,
-    html_content: String,
+    html_template: Template,
+    text_template: Template,
 }
 
 #[tracing::instrument(skip_all)]
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
-    let issue = sqlx::query_as!(
-        NewsletterIssue,
+    let issue = sqlx::query!(
         r#"
         SELECT 
             title,
@@ -143,25 +527,186 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     )
     .fetch_one(pool)
     .await?;
-    Ok(issue)
+    Ok(NewsletterIssue {
+        title: issue.title,
+        html_template: parse_template(issue_id, &issue.html_content),
+        text_template: parse_template(issue_id, &issue.text_content),
+    })
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
+/// Templates are validated when an issue is published, but issues published
+/// before placeholders existed may contain stray braces: those are sent as
+/// they are rather than not at all.
+fn parse_template(issue_id: Uuid, content: &str) -> Template {
+    Template::parse(content).unwrap_or_else(|e| {
+        tracing::warn!(
+            error.message = %e,
+            newsletter_issue_id = %issue_id,
+            "Sending an issue body without rendering its placeholders"
+        );
+        Template::verbatim(content)
+    })
+}
+
+/// Executes tasks until shutdown is requested. A batch that is already being
+/// sent is always settled and committed before the loop exits.
+///
+/// An idle worker wakes up as soon as an issue is published; polling the
+/// queue every `empty_queue_poll_interval` covers notifications that got lost
+/// and tasks that were scheduled for a later retry.
+///
+/// While the email provider refuses our account, all workers are paused
+/// for longer and longer, as failed deliveries are.
+#[allow(clippy::too_many_arguments)]
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    rate_limiter: Arc<RateLimiter>,
+    settings: IssueDeliverySettings,
+    base_url: Arc<ApplicationBaseUrl>,
+    hmac_secret: Arc<HmacSecret>,
+    new_deliveries: Arc<Notify>,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    let mut n_account_errors: i16 = 0;
+    while !shutdown.is_requested() {
+        // Register interest before looking at the queue, so that an issue
+        // published in the meantime still wakes us up.
+        let new_delivery = new_deliveries.notified();
+        tokio::pin!(new_delivery);
+        new_delivery.as_mut().enable();
+        // After a 429 this can take a while: it must not hold up shutdown.
+        tokio::select! {
+            _ = rate_limiter.acquire(batch_tokens(&settings)) => {}
+            _ = shutdown.requested() => break,
+        }
+        match execute_batch(
+            &pool,
+            &email_client,
+            &rate_limiter,
+            &settings,
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
             Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+                tokio::select! {
+                    _ = shutdown.sleep(settings.empty_queue_poll_interval()) => {}
+                    _ = new_delivery => {}
+                }
             }
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            Err(e) => {
+                if matches!(e.downcast_ref::<SendEmailError>(), Some(e) if e.is_account_level()) {
+                    let pause = retry_backoff(&settings, n_account_errors);
+                    n_account_errors = n_account_errors.saturating_add(1);
+                    tracing::warn!(
+                        pause = ?pause,
+                        "Pausing deliveries until the email provider accepts our account again."
+                    );
+                    rate_limiter.pause(pause).await;
+                }
+                shutdown.sleep(settings.error_poll_interval()).await;
             }
-            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::TaskCompleted) => n_account_errors = 0,
         }
     }
+    Ok(())
 }
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+/// Forwards the notifications sent on [`NEW_DELIVERIES_CHANNEL`] to the
+/// workers. Losing the connection is not fatal, the workers keep polling
+/// while we reconnect.
+async fn listen_for_new_deliveries(
+    pool: PgPool,
+    new_deliveries: Arc<Notify>,
+    retry_interval: Duration,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_requested() {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to connect to Postgres to listen for new deliveries"
+                );
+                shutdown.sleep(retry_interval).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(NEW_DELIVERIES_CHANNEL).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to listen for new deliveries"
+            );
+            shutdown.sleep(retry_interval).await;
+            continue;
+        }
+        loop {
+            tokio::select! {
+                notification = listener.recv() => match notification {
+                    Ok(notification) => {
+                        tracing::debug!(
+                            newsletter_issue_id = notification.payload(),
+                            "Waking up the workers for a new issue"
+                        );
+                        new_deliveries.notify_waiters();
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Stopped receiving notifications for new deliveries"
+                        );
+                        shutdown.sleep(retry_interval).await;
+                        break;
+                    }
+                },
+                _ = shutdown.requested() => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs `n_workers` delivery workers sharing one email client and one rate
+/// limiter, and returns once all of them have stopped.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    configuration.issue_delivery.validate()?;
     let connection_pool = get_connection_pool(&configuration.database);
-    let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    let email_client = Arc::new(configuration.email_client.client());
+    let rate_limiter = Arc::new(configuration.issue_delivery.rate_limiter()?);
+    let base_url = Arc::new(ApplicationBaseUrl(configuration.application.base_url));
+    let hmac_secret = Arc::new(HmacSecret(configuration.application.hmac_secret));
+    let new_deliveries = Arc::new(Notify::new());
+    let listener = tokio::spawn(listen_for_new_deliveries(
+        connection_pool.clone(),
+        new_deliveries.clone(),
+        configuration.issue_delivery.error_poll_interval(),
+        shutdown.clone(),
+    ));
+    let workers: Vec<_> = (0..configuration.issue_delivery.n_workers)
+        .map(|_| {
+            tokio::spawn(worker_loop(
+                connection_pool.clone(),
+                email_client.clone(),
+                rate_limiter.clone(),
+                configuration.issue_delivery.clone(),
+                base_url.clone(),
+                hmac_secret.clone(),
+                new_deliveries.clone(),
+                shutdown.clone(),
+            ))
+        })
+        .collect();
+    for worker in workers {
+        worker.await??;
+    }
+    listener.await?
 }
@@ -6,8 +6,13 @@ pub mod domain;
 pub mod email_client;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod markdown;
+pub mod rate_limiter;
 pub mod routes;
+pub mod sanitize;
 pub mod session_state;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod template;
 pub mod utils;
@@ -4,7 +4,9 @@ use std::fmt::{Debug, Display};
 
 use tokio::task::JoinError;
 use zero2prod::configuration::get_configuration;
+use zero2prod::idempotency::run_expiry_worker_until_stopped;
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::shutdown;
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -16,14 +18,41 @@ async fn main() -> anyhow::Result<()> {
     let configuration = get_configuration().expect("failed to read configuration.");
 
     let application = Application::build(configuration.clone()).await?;
+    let (shutdown_sender, shutdown) = shutdown::channel();
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    let mut worker_task = tokio::spawn(run_worker_until_stopped(
+        configuration.clone(),
+        shutdown.clone(),
+    ));
+    let mut expiry_task = tokio::spawn(run_expiry_worker_until_stopped(configuration, shutdown));
 
-    tokio::select! {
-        o = application_task => report_exit("API", o),
-        o = worker_task => report_exit("Background worker", o),
+    // actix-web stops the API by itself on SIGTERM/SIGINT, after draining the
+    // requests in flight.
+    let stopped = tokio::select! {
+        o = application_task => {
+            report_exit("API", o);
+            "API"
+        }
+        o = &mut worker_task => {
+            report_exit("Background worker", o);
+            "Background worker"
+        }
+        o = &mut expiry_task => {
+            report_exit("Idempotency expiry worker", o);
+            "Idempotency expiry worker"
+        }
     };
 
+    // Let the workers settle and commit the tasks they are holding instead of
+    // dropping them mid-transaction when `main` returns.
+    let _ = shutdown_sender.send(true);
+    if stopped != "Background worker" {
+        report_exit("Background worker", worker_task.await);
+    }
+    if stopped != "Idempotency expiry worker" {
+        report_exit("Idempotency expiry worker", expiry_task.await);
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,207 @@
+//! src/markdown.rs
+
+use crate::sanitize;
+use htmlescape::encode_minimal;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+
+fn parser(markdown: &str) -> Parser<'_, '_> {
+    Parser::new_ext(
+        markdown,
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
+    )
+}
+
+/// Drops the spaces inside `{{ placeholder }}`, which would otherwise stop a
+/// placeholder from being used as a link target.
+fn tighten_placeholders(markdown: &str) -> String {
+    let mut tightened = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        tightened.push_str(&rest[..start]);
+        tightened.push_str("{{");
+        tightened.push_str(rest[start + 2..end].trim());
+        tightened.push_str("}}");
+        rest = &rest[end + 2..];
+    }
+    tightened.push_str(rest);
+    tightened
+}
+
+/// Renders Markdown to HTML. Raw HTML embedded in the Markdown goes through
+/// the same sanitizer as everything else, so it cannot smuggle scripts in.
+pub fn to_html(markdown: &str) -> String {
+    let markdown = tighten_placeholders(markdown);
+    // Link targets are percent-encoded, which would hide a placeholder such
+    // as `{{unsubscribe_url}}` from the template: those links are written
+    // out as they are.
+    let events = parser(&markdown).map(|event| match event {
+        Event::Start(Tag::Link(_, url, title)) if url.contains("{{") => {
+            let title = if title.is_empty() {
+                String::new()
+            } else {
+                format!(r#" title="{}""#, encode_minimal(&title))
+            };
+            Event::Html(format!(r#"<a href="{}"{}>"#, encode_minimal(&url), title).into())
+        }
+        event => event,
+    });
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events);
+    sanitize::html(&unsafe_html)
+}
+
+/// Renders Markdown to a plain-text alternative that reads well in a mail
+/// client: markup is dropped, list items get a bullet and links keep their
+/// target next to their text.
+pub fn to_text(markdown: &str) -> String {
+    let markdown = tighten_placeholders(markdown);
+    let mut text = String::new();
+    // One entry per open list, with the number of the next item for ordered
+    // lists.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    // Where the text of each open link or image starts, and its target.
+    let mut links: Vec<(usize, String)> = Vec::new();
+    for event in parser(&markdown) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----\n\n"),
+            Event::Start(Tag::List(first)) => {
+                if lists.is_empty() {
+                    end_line(&mut text);
+                }
+                lists.push(first);
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_line(&mut text);
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                end_line(&mut text);
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        text.push_str(&format!("{}. ", n));
+                        *n += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::Link(_, url, _) | Tag::Image(_, url, _)) => {
+                links.push((text.len(), url.to_string()));
+            }
+            Event::End(Tag::Link(..) | Tag::Image(..)) => {
+                if let Some((start, url)) = links.pop() {
+                    if text[start..] != url {
+                        text.push_str(&format!(" ({})", url));
+                    }
+                }
+            }
+            Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::CodeBlock(_)) => {
+                end_line(&mut text);
+                // Blocks are separated by a blank line, except within lists.
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::End(Tag::TableCell) => text.push('\t'),
+            Event::End(Tag::TableHead | Tag::TableRow) => end_line(&mut text),
+            _ => {}
+        }
+    }
+    text.trim_end().to_string()
+}
+
+/// Makes sure that the next piece of text starts on a new line.
+fn end_line(text: &mut String) {
+    while text.ends_with(' ') || text.ends_with('\t') {
+        text.pop();
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_html, to_text};
+    use crate::template::{Recipient, Template};
+    use chrono::Utc;
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html = to_html("# Title\n\nSome *emphasis* and a [link](https://example.com).");
+
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<em>emphasis</em>"));
+        assert!(html.contains(r#"<a href="https://example.com">link</a>"#));
+    }
+
+    #[test]
+    fn placeholders_used_as_link_targets_are_rendered_per_recipient() {
+        let html = to_html("[Unsubscribe]({{ unsubscribe_url }}) from {{ name }}'s list");
+        let hand_written =
+            r#"<p><a href="{{ unsubscribe_url }}">Unsubscribe</a> from {{ name }}'s list</p>"#;
+        let recipient = Recipient {
+            name: "Ursula",
+            unsubscribe_url: "https://example.com/subscriptions/unsubscribe?token=abc",
+            subscribed_at: Utc::now(),
+        };
+
+        assert_eq!(
+            Template::parse(html.trim())
+                .unwrap()
+                .render_html(&recipient),
+            Template::parse(hand_written)
+                .unwrap()
+                .render_html(&recipient)
+        );
+    }
+
+    #[test]
+    fn raw_html_in_markdown_is_sanitized() {
+        let html = to_html("Hello<script>alert(1)</script> <b onclick=\"steal()\">there</b>");
+
+        assert!(!html.contains("script"));
+        assert!(!html.contains("onclick"));
+        assert!(html.contains("<b>there</b>"));
+    }
+
+    #[test]
+    fn paragraphs_and_headings_are_separated_by_blank_lines() {
+        let text = to_text("# Title\n\nFirst *paragraph*.\n\nSecond `paragraph`.");
+
+        assert_eq!(text, "Title\n\nFirst paragraph.\n\nSecond paragraph.");
+    }
+
+    #[test]
+    fn list_items_get_bullets_or_numbers() {
+        let text = to_text("Intro\n\n- one\n- two\n  1. nested\n\nOutro");
+
+        assert_eq!(text, "Intro\n\n- one\n- two\n  1. nested\n\nOutro");
+    }
+
+    #[test]
+    fn links_keep_their_target() {
+        let text = to_text("Read [the docs](https://example.com/docs) or <https://example.com>.");
+
+        assert_eq!(
+            text,
+            "Read the docs (https://example.com/docs) or https://example.com."
+        );
+    }
+
+    #[test]
+    fn raw_html_is_left_out_of_the_text() {
+        let text = to_text("Hello <span>there</span>");
+
+        assert_eq!(text, "Hello there");
+    }
+}
@@ -0,0 +1,186 @@
+//! src/rate_limiter.rs
+
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// A token bucket, shared by all the tasks of a process that send emails.
+///
+/// Tokens are handed out on credit: a caller asking for more tokens than the
+/// bucket holds gets them straight away but waits until the debt has been
+/// repaid, so that concurrent callers are served in order.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    /// Tokens accrue from this instant on. It lies in the future while the
+    /// provider asked us to back off.
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Fails unless the rate is a positive number and at least one message
+    /// can be sent at once.
+    pub fn new(per_second: f64, burst: u32) -> Result<Self, String> {
+        if !(per_second.is_finite() && per_second > 0.0) {
+            return Err(format!(
+                "The rate limit must be a positive number of messages per second, got {}.",
+                per_second
+            ));
+        }
+        if burst == 0 {
+            return Err("The rate limit burst must be at least 1.".into());
+        }
+        let burst = f64::from(burst);
+        Ok(Self {
+            per_second,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// Waits until `n` messages can be sent without exceeding the rate limit.
+    /// A pause that starts in the meantime holds up the callers that are
+    /// already waiting as well.
+    pub async fn acquire(&self, n: u32) {
+        let mut wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            self.refill(&mut bucket, now);
+            bucket.tokens -= f64::from(n);
+            let debt = Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.per_second);
+            bucket.refilled_at.saturating_duration_since(now) + debt
+        };
+        while !wait.is_zero() {
+            tracing::debug!(wait = ?wait, "Waiting for the outbound rate limit");
+            tokio::time::sleep(wait).await;
+            let bucket = self.bucket.lock().await;
+            wait = bucket.refilled_at.saturating_duration_since(Instant::now());
+        }
+    }
+
+    /// Gives back tokens that were acquired but turned out not to be needed.
+    pub async fn release(&self, n: u32) {
+        let mut bucket = self.bucket.lock().await;
+        self.refill(&mut bucket, Instant::now());
+        bucket.tokens = (bucket.tokens + f64::from(n)).min(self.burst);
+    }
+
+    /// Stops handing out tokens for `duration`, e.g. when the provider
+    /// answered with a 429 and a `Retry-After` header.
+    pub async fn pause(&self, duration: Duration) {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        self.refill(&mut bucket, now);
+        bucket.tokens = bucket.tokens.min(0.0);
+        bucket.refilled_at = bucket.refilled_at.max(now + duration);
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        bucket.refilled_at = bucket.refilled_at.max(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use claims::assert_err;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn rates_that_are_not_positive_are_rejected() {
+        assert_err!(RateLimiter::new(0.0, 10));
+        assert_err!(RateLimiter::new(-1.0, 10));
+        assert_err!(RateLimiter::new(f64::NAN, 10));
+        assert_err!(RateLimiter::new(f64::INFINITY, 10));
+    }
+
+    #[test]
+    fn an_empty_burst_is_rejected() {
+        assert_err!(RateLimiter::new(10.0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_burst_is_available_immediately() {
+        let rate_limiter = RateLimiter::new(1.0, 10).unwrap();
+        let start = Instant::now();
+
+        rate_limiter.acquire(10).await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn going_over_the_burst_waits_for_the_tokens_to_be_refilled() {
+        let rate_limiter = RateLimiter::new(10.0, 10).unwrap();
+        let start = Instant::now();
+
+        rate_limiter.acquire(10).await;
+        rate_limiter.acquire(5).await;
+
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_do_not_accumulate_beyond_the_burst() {
+        let rate_limiter = RateLimiter::new(10.0, 10).unwrap();
+        rate_limiter.acquire(10).await;
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let start = Instant::now();
+
+        rate_limiter.acquire(20).await;
+
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_pause_also_delays_callers_that_are_already_waiting() {
+        let rate_limiter = RateLimiter::new(10.0, 10).unwrap();
+        rate_limiter.acquire(10).await;
+        let start = Instant::now();
+
+        let waiting = rate_limiter.acquire(10);
+        let pausing = async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            rate_limiter.pause(Duration::from_secs(30)).await;
+        };
+        tokio::join!(waiting, pausing);
+
+        assert_eq!(start.elapsed(), Duration::from_millis(30_500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn released_tokens_can_be_acquired_again() {
+        let rate_limiter = RateLimiter::new(10.0, 10).unwrap();
+        rate_limiter.acquire(10).await;
+        rate_limiter.release(4).await;
+        let start = Instant::now();
+
+        rate_limiter.acquire(4).await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_pause_delays_the_next_acquisition() {
+        let rate_limiter = RateLimiter::new(10.0, 10).unwrap();
+        let start = Instant::now();
+
+        rate_limiter.pause(Duration::from_secs(30)).await;
+        rate_limiter.acquire(1).await;
+
+        assert_eq!(start.elapsed(), Duration::from_millis(30_100));
+    }
+}
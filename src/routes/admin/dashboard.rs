@@ -35,6 +35,11 @@ pub async fn admin_dashboard(
                         <ol>
                             <li><a href="/admin/password">Change password</a></li>
                             <li><a href="/admin/newsletters">Send newsletters</a></li>
+                            <li><a href="/admin/newsletters/drafts">Newsletter drafts</a></li>
+                            <li><a href="/admin/newsletters/scheduled">Scheduled newsletters</a></li>
+                            <li><a href="/admin/deliveries/failed">Failed deliveries</a></li>
+                            <li><a href="/admin/lists">Mailing lists</a></li>
+                            <li><a href="/admin/subscribers/tags">Subscriber tags</a></li>
                             <li>
                               <form name="logoutForm" action="/admin/logout" method="post">
                                 <input type="submit" value="Logout">
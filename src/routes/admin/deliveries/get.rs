@@ -0,0 +1,121 @@
+//! src/routes/admin/deliveries/get.rs
+
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct FailedDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i16,
+    error_chain: String,
+    enqueued_at: DateTime<Utc>,
+    failed_at: DateTime<Utc>,
+}
+
+pub async fn failed_deliveries_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let failed_deliveries = get_failed_deliveries(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for delivery in &failed_deliveries {
+        writeln!(
+            rows_html,
+            r#"
+                <tr>
+                    <td><a href="/admin/newsletters/{issue_id}">{title}</a></td>
+                    <td>{email}</td>
+                    <td>{attempts}</td>
+                    <td><pre>{error_chain}</pre></td>
+                    <td>{enqueued_at}</td>
+                    <td>{failed_at}</td>
+                    <td>
+                        <form action="/admin/deliveries/failed/requeue" method="post">
+                            <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                            <input hidden type="text" name="subscriber_email" value="{email}">
+                            <button type="submit">Requeue</button>
+                        </form>
+                    </td>
+                </tr>"#,
+            title = encode_minimal(&delivery.title),
+            email = encode_minimal(&delivery.subscriber_email),
+            attempts = delivery.n_retries + 1,
+            error_chain = encode_minimal(&delivery.error_chain),
+            enqueued_at = delivery.enqueued_at.to_rfc3339(),
+            failed_at = delivery.failed_at.to_rfc3339(),
+            issue_id = delivery.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+    let n_failed = failed_deliveries.len();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Failed deliveries</title>
+        </head>
+        <body>
+            {msg_html}
+            <p>{n_failed} failed deliveries.</p>
+            <form action="/admin/deliveries/failed/requeue_all" method="post">
+                <button type="submit">Requeue all</button>
+            </form>
+            <table>
+                <tr>
+                    <th>Issue</th>
+                    <th>Recipient</th>
+                    <th>Attempts</th>
+                    <th>Error</th>
+                    <th>Enqueued at</th>
+                    <th>Failed at</th>
+                    <th></th>
+                </tr>
+                {rows_html}
+            </table>
+            <p><a href="/admin/dashboard">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_failed_deliveries(pool: &PgPool) -> Result<Vec<FailedDelivery>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT
+            f.newsletter_issue_id,
+            n.title,
+            f.subscriber_email,
+            f.n_retries,
+            f.error_chain,
+            f.enqueued_at,
+            f.failed_at
+        FROM failed_deliveries f
+        JOIN newsletter_issues n USING (newsletter_issue_id)
+        ORDER BY f.failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve failed deliveries.")?;
+    Ok(rows)
+}
@@ -0,0 +1,7 @@
+//! src/routes/admin/deliveries/mod.rs
+
+mod get;
+mod post;
+
+pub use get::*;
+pub use post::*;
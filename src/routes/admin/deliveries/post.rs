@@ -0,0 +1,120 @@
+//! src/routes/admin/deliveries/post.rs
+
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(
+    name = "Requeue a failed delivery",
+    skip_all,
+    fields(
+        newsletter_issue_id = %form.newsletter_issue_id,
+        subscriber_email = %form.subscriber_email
+    )
+)]
+pub async fn requeue_failed_delivery(
+    form: web::Form<RequeueFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The failure is only forgotten once the delivery is back in the queue:
+    // if it is queued already, the insert does nothing and the failure stays.
+    let n_requeued = sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            SELECT newsletter_issue_id, subscriber_email
+            FROM failed_deliveries
+            WHERE
+                newsletter_issue_id = $1
+            AND subscriber_email = $2
+            ON CONFLICT DO NOTHING
+            RETURNING newsletter_issue_id, subscriber_email
+        ),
+        ledger AS (
+            UPDATE newsletter_issue_deliveries d
+            SET
+                status = 'pending',
+                updated_at = now()
+            FROM inserted i
+            WHERE
+                d.newsletter_issue_id = i.newsletter_issue_id
+            AND d.subscriber_email = i.subscriber_email
+        )
+        DELETE FROM failed_deliveries f
+        USING inserted i
+        WHERE
+            f.newsletter_issue_id = i.newsletter_issue_id
+        AND f.subscriber_email = i.subscriber_email
+        "#,
+        form.newsletter_issue_id,
+        form.subscriber_email,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to requeue a failed delivery.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_requeued > 0 {
+        FlashMessage::info(format!(
+            "The delivery to {} has been requeued.",
+            encode_minimal(&form.subscriber_email)
+        ))
+        .send();
+    } else {
+        FlashMessage::error("The delivery could not be found or is already queued.").send();
+    }
+    Ok(see_other("/admin/deliveries/failed"))
+}
+
+#[tracing::instrument(name = "Requeue all failed deliveries", skip_all)]
+pub async fn requeue_all_failed_deliveries(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_requeued = sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            SELECT newsletter_issue_id, subscriber_email
+            FROM failed_deliveries
+            ON CONFLICT DO NOTHING
+            RETURNING newsletter_issue_id, subscriber_email
+        ),
+        ledger AS (
+            UPDATE newsletter_issue_deliveries d
+            SET
+                status = 'pending',
+                updated_at = now()
+            FROM inserted i
+            WHERE
+                d.newsletter_issue_id = i.newsletter_issue_id
+            AND d.subscriber_email = i.subscriber_email
+        )
+        DELETE FROM failed_deliveries f
+        USING inserted i
+        WHERE
+            f.newsletter_issue_id = i.newsletter_issue_id
+        AND f.subscriber_email = i.subscriber_email
+        "#
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to requeue failed deliveries.")
+    .map_err(e500)?
+    .rows_affected();
+    FlashMessage::info(format!(
+        "{} failed deliveries have been requeued.",
+        n_requeued
+    ))
+    .send();
+    Ok(see_other("/admin/deliveries/failed"))
+}
@@ -0,0 +1,135 @@
+//! src/routes/admin/lists.rs
+
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+struct ListSummary {
+    name: String,
+    is_default: bool,
+    n_confirmed: i64,
+    n_pending: i64,
+}
+
+pub async fn lists_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let lists = get_list_summaries(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for list in &lists {
+        writeln!(
+            rows_html,
+            r#"
+                <tr>
+                    <td>{name}{default}</td>
+                    <td>{n_confirmed}</td>
+                    <td>{n_pending}</td>
+                </tr>"#,
+            name = encode_minimal(&list.name),
+            default = if list.is_default { " (default)" } else { "" },
+            n_confirmed = list.n_confirmed,
+            n_pending = list.n_pending,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Mailing lists</title>
+        </head>
+        <body>
+            {msg_html}
+            <table>
+                <tr>
+                    <th>List</th>
+                    <th>Confirmed</th>
+                    <th>Pending confirmation</th>
+                </tr>
+                {rows_html}
+            </table>
+            <form action="/admin/lists" method="post">
+                <label>New list:
+                    <input type="text" name="name" placeholder="Enter the list name">
+                </label>
+                <button type="submit">Create</button>
+            </form>
+            <p><a href="/admin/dashboard">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct NewListFormData {
+    name: String,
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(pool))]
+pub async fn create_list(
+    form: web::Form<NewListFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.0.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The list needs a name.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, name)
+        VALUES ($1, $2)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        uuid::Uuid::new_v4(),
+        name,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to create a mailing list.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted == 0 {
+        FlashMessage::error("A list with this name already exists.").send();
+    } else {
+        FlashMessage::info("The list has been created.").send();
+    }
+    Ok(see_other("/admin/lists"))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_list_summaries(pool: &PgPool) -> Result<Vec<ListSummary>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.name,
+            l.is_default,
+            count(*) FILTER (WHERE m.status = 'confirmed') as "n_confirmed!",
+            count(*) FILTER (WHERE m.status = 'pending_confirmation') as "n_pending!"
+        FROM lists l
+        LEFT JOIN list_memberships m ON m.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.is_default DESC, l.name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve mailing lists.")?;
+    Ok(lists)
+}
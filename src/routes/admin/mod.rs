@@ -1,11 +1,17 @@
 //! src/routes/admin/mod.rs
 
 mod dashboard;
+mod deliveries;
+mod lists;
 mod logout;
 mod newsletters;
 mod password;
+mod tags;
 
 pub use dashboard::admin_dashboard;
+pub use deliveries::*;
+pub use lists::*;
 pub use logout::log_out;
 pub use newsletters::*;
 pub use password::*;
+pub use tags::*;
@@ -0,0 +1,578 @@
+//! src/routes/admin/newsletters/drafts.rs
+//!
+//! A draft is a newsletter issue that has not been published yet
+//! (`published_at IS NULL`): it can be edited, previewed and sent to the
+//! admin address as many times as needed before going out.
+
+use super::get::{audience_filter_html, target_lists_html};
+use super::post::{
+    bodies_from_markdown, enqueue_delivery_tasks, sanitize_html_content, sanitized_warning,
+    set_target_lists, success_message, unknown_list_message, validate_templates,
+};
+use crate::domain::AudienceFilter;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::render_issue;
+use crate::routes::{get_lists, parse_scheduled_for};
+use crate::sanitize;
+use crate::startup::{AdminEmail, ApplicationBaseUrl};
+use crate::template::{Recipient, Template, TemplateError};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use actix_web_lab::extract::UrlEncodedForm;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct Draft {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    markdown_content: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    markdown_content: Option<String>,
+}
+
+impl DraftFormData {
+    fn bodies(&self) -> (String, String) {
+        bodies_from_markdown(
+            self.markdown_content(),
+            self.html_content.clone(),
+            self.text_content.clone(),
+        )
+    }
+
+    fn markdown_content(&self) -> Option<&str> {
+        self.markdown_content
+            .as_deref()
+            .filter(|s| !s.trim().is_empty())
+    }
+}
+
+impl Draft {
+    /// The bodies to show in the editor. Those generated from the Markdown
+    /// source are left empty, so that editing the Markdown generates them
+    /// again; those written by hand take precedence and are kept.
+    fn hand_written_bodies(&self) -> (&str, &str) {
+        let markdown_content = match self.markdown_content.as_deref() {
+            Some(markdown_content) => markdown_content,
+            None => return (&self.html_content, &self.text_content),
+        };
+        let (html_content, text_content) =
+            bodies_from_markdown(Some(markdown_content), String::new(), String::new());
+        (
+            if self.html_content == html_content {
+                ""
+            } else {
+                &self.html_content
+            },
+            if self.text_content == text_content {
+                ""
+            } else {
+                &self.text_content
+            },
+        )
+    }
+}
+
+pub async fn drafts_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let msg_html = flash_messages_html(&flash_messages);
+    let drafts = get_drafts(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for draft in &drafts {
+        writeln!(
+            rows_html,
+            r#"
+                <tr>
+                    <td><a href="/admin/newsletters/drafts/{issue_id}">{title}</a></td>
+                    <td>{updated_at}</td>
+                    <td><a href="/admin/newsletters/drafts/{issue_id}/preview">Preview</a></td>
+                </tr>"#,
+            issue_id = draft.newsletter_issue_id,
+            title = encode_minimal(&draft.title),
+            updated_at = draft.updated_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    let n_drafts = drafts.len();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Newsletter drafts</title>
+        </head>
+        <body>
+            {msg_html}
+            <p>{n_drafts} drafts. <a href="/admin/newsletters">Write a new issue</a></p>
+            <table>
+                <tr>
+                    <th>Issue</th>
+                    <th>Last saved at</th>
+                    <th></th>
+                </tr>
+                {rows_html}
+            </table>
+            <p><a href="/admin/dashboard">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(name = "Save a new newsletter draft", skip_all)]
+pub async fn create_draft(
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let (html_content, text_content) = form.bodies();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        newsletter_issue_id,
+        form.title,
+        text_content,
+        html_content,
+        form.markdown_content(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store a newsletter draft.")
+    .map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        newsletter_issue_id
+    )))
+}
+
+#[tracing::instrument(name = "Show a newsletter draft", skip(flash_messages, pool))]
+pub async fn edit_draft_form(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = match get_draft(&pool, *newsletter_issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let msg_html = flash_messages_html(&flash_messages);
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    let target_lists_html = target_lists_html(&lists);
+    let audience_filter_html = audience_filter_html();
+    let issue_id = draft.newsletter_issue_id;
+    let title = encode_attribute(&draft.title);
+    let markdown_content = encode_minimal(draft.markdown_content.as_deref().unwrap_or_default());
+    let (html_content, text_content) = draft.hand_written_bodies();
+    let html_content = encode_minimal(html_content);
+    let text_content = encode_minimal(text_content);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Edit draft</title>
+        </head>
+        <body>
+            {msg_html}
+            <form action="/admin/newsletters/drafts/{issue_id}" method="post">
+                <label>Newsletter Title:<br>
+                    <input type="text" name="title" value="{title}">
+                </label>
+                <br>
+                <label>Markdown newsletter (fills in the HTML and plain text versions left empty):<br>
+                    <textarea name="markdown_content" rows="25" cols="65">{markdown_content}</textarea>
+                </label>
+                <br>
+                <label>HTML newsletter:<br>
+                    <textarea name="html_content" rows="25" cols="65">{html_content}</textarea>
+                </label>
+                <br>
+                <label>Plain text newsletter:<br>
+                    <textarea name="text_content" rows="25" cols="65">{text_content}</textarea>
+                </label>
+                <br>
+                <button type="submit">Save draft</button>
+            </form>
+            <p><a href="/admin/newsletters/drafts/{issue_id}/preview">Preview</a></p>
+            <form action="/admin/newsletters/drafts/{issue_id}/test" method="post">
+                <button type="submit">Send a test email to myself</button>
+            </form>
+            <form action="/admin/newsletters/drafts/{issue_id}/publish" method="post">
+                {target_lists_html}
+                {audience_filter_html}
+                <label>Schedule for (UTC, leave empty to send right away):<br>
+                    <input type="datetime-local" name="scheduled_for">
+                </label>
+                <button type="submit">Publish</button>
+            </form>
+            <p><a href="/admin/newsletters/drafts">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(name = "Update a newsletter draft", skip(form, pool))]
+pub async fn save_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (html_content, text_content) = form.bodies();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            markdown_content = $5,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1
+        AND published_at IS NULL
+        "#,
+        *newsletter_issue_id,
+        form.title,
+        text_content,
+        html_content,
+        form.markdown_content(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update a newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error(no_longer_a_draft_message()).send();
+        return Ok(see_other("/admin/newsletters/drafts"));
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        newsletter_issue_id
+    )))
+}
+
+#[tracing::instrument(name = "Preview a newsletter draft", skip(pool, base_url))]
+pub async fn preview_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = match get_draft(&pool, *newsletter_issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (html_body, text_body) = match render_draft(&draft, &base_url) {
+        Ok(bodies) => bodies,
+        Err(e) => return Ok(invalid_template_redirect(&draft, &e)),
+    };
+    let issue_id = draft.newsletter_issue_id;
+    let title = encode_minimal(&draft.title);
+    // The HTML body goes in an iframe, so that its markup and styles cannot
+    // leak into the page around it.
+    let html_body = encode_attribute(&html_body);
+    let text_body = encode_minimal(&text_body);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Preview: {title}</title>
+        </head>
+        <body>
+            <h1>{title}</h1>
+            <h2>HTML</h2>
+            <iframe sandbox srcdoc="{html_body}" width="100%" height="600"></iframe>
+            <h2>Plain text</h2>
+            <pre>{text_body}</pre>
+            <p><a href="/admin/newsletters/drafts/{issue_id}">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Send a test copy of a newsletter draft",
+    skip(pool, email_client, admin_email, base_url)
+)]
+pub async fn send_test_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    admin_email: web::Data<AdminEmail>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = match get_draft(&pool, *newsletter_issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (html_body, text_body) = match render_draft(&draft, &base_url) {
+        Ok(bodies) => bodies,
+        Err(e) => return Ok(invalid_template_redirect(&draft, &e)),
+    };
+    let subject = format!("[Test] {}", draft.title);
+    match email_client
+        .send_email(&admin_email.0, &subject, &html_body, &text_body)
+        .await
+    {
+        Ok(()) => FlashMessage::info(format!("A test email has been sent to {}.", admin_email.0)),
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a test email"
+            );
+            FlashMessage::error(encode_minimal(&format!(
+                "The test email could not be sent: {}",
+                e
+            )))
+        }
+    }
+    .send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        newsletter_issue_id
+    )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftFormData {
+    scheduled_for: Option<String>,
+    /// One `list_id` field per target list; the default list if there are none.
+    #[serde(default, rename = "list_id")]
+    list_ids: Vec<Uuid>,
+    /// Left empty to send the issue to every member of the target lists.
+    audience_filter: Option<String>,
+}
+
+#[tracing::instrument(name = "Publish a newsletter draft", skip(form, pool))]
+pub async fn publish_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: UrlEncodedForm<PublishDraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let scheduled_for = match parse_scheduled_for(form.scheduled_for.as_deref()) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other(&format!(
+                "/admin/newsletters/drafts/{}",
+                newsletter_issue_id
+            )));
+        }
+    };
+    let audience_filter = form.audience_filter.as_deref().map(str::trim);
+    let parsed_audience_filter = match AudienceFilter::parse(audience_filter.unwrap_or_default()) {
+        Ok(audience_filter) => audience_filter,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other(&format!(
+                "/admin/newsletters/drafts/{}",
+                newsletter_issue_id
+            )));
+        }
+    };
+    let draft = match get_draft(&pool, newsletter_issue_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => {
+            FlashMessage::error(no_longer_a_draft_message()).send();
+            return Ok(see_other("/admin/newsletters/drafts"));
+        }
+    };
+    let (html_content, was_sanitized) = sanitize_html_content(draft.html_content.clone());
+    if let Err(e) = validate_templates(&html_content, &draft.text_content) {
+        return Ok(invalid_template_redirect(&draft, &e));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    // Publishing flips `published_at` only once, so a second click (or a
+    // second tab) cannot enqueue the issue twice.
+    let n_published = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            published_at = now(),
+            scheduled_for = $2,
+            html_content = $3,
+            audience_filter = $4
+        WHERE
+            newsletter_issue_id = $1
+        AND published_at IS NULL
+        "#,
+        newsletter_issue_id,
+        scheduled_for,
+        html_content,
+        audience_filter.filter(|s| !s.is_empty()),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to publish a newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_published == 0 {
+        FlashMessage::error(no_longer_a_draft_message()).send();
+        return Ok(see_other("/admin/newsletters/drafts"));
+    }
+    let all_lists_exist = set_target_lists(&mut transaction, newsletter_issue_id, &form.list_ids)
+        .await
+        .context("Failed to store the target lists of a newsletter draft.")
+        .map_err(e500)?;
+    if !all_lists_exist {
+        FlashMessage::error(unknown_list_message()).send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/drafts/{}",
+            newsletter_issue_id
+        )));
+    }
+    enqueue_delivery_tasks(
+        &mut transaction,
+        newsletter_issue_id,
+        scheduled_for,
+        parsed_audience_filter.as_ref(),
+    )
+    .await
+    .context("Failed to enqueue delievery tasks")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter draft.")
+        .map_err(e500)?;
+    success_message(scheduled_for).send();
+    if was_sanitized {
+        sanitized_warning().send();
+    }
+    Ok(see_other("/admin/newsletters/drafts"))
+}
+
+/// Renders a draft the way subscribers will receive it, for a made-up
+/// subscriber and with the HTML sanitized as it will be on publish. The
+/// unsubscribe link points to the unsubscribe page without a token, since
+/// nobody is being unsubscribed.
+fn render_draft(
+    draft: &Draft,
+    base_url: &ApplicationBaseUrl,
+) -> Result<(String, String), TemplateError> {
+    let unsubscribe_url = format!("{}/subscriptions/unsubscribe", base_url.0);
+    let recipient = Recipient {
+        name: "Jane Doe",
+        unsubscribe_url: &unsubscribe_url,
+        subscribed_at: Utc::now(),
+    };
+    Ok(render_issue(
+        &Template::parse(&sanitize::html(&draft.html_content))?,
+        &Template::parse(&draft.text_content)?,
+        &recipient,
+    ))
+}
+
+/// Sends the admin back to the editor to fix the placeholders of a draft.
+fn invalid_template_redirect(draft: &Draft, e: &TemplateError) -> HttpResponse {
+    FlashMessage::error(encode_minimal(&e.to_string())).send();
+    see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        draft.newsletter_issue_id
+    ))
+}
+
+fn flash_messages_html(flash_messages: &IncomingFlashMessages) -> String {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    msg_html
+}
+
+fn no_longer_a_draft_message() -> &'static str {
+    "This issue has already been published and can no longer be changed."
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            updated_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a newsletter draft.")?;
+    Ok(draft)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_drafts(pool: &PgPool) -> Result<Vec<Draft>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            updated_at
+        FROM newsletter_issues
+        WHERE published_at IS NULL
+        ORDER BY updated_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve newsletter drafts.")?;
+    Ok(drafts)
+}
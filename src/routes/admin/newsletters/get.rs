@@ -1,17 +1,25 @@
 //! src/routes/admin/newsletters/get.rs
 
+use crate::routes::{get_lists, MailingList};
+use crate::utils::e500;
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
 use std::fmt::Write;
 
 pub async fn publish_newsletter_form(
     flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    let target_lists_html = target_lists_html(&lists);
+    let audience_filter_html = audience_filter_html();
     let idempotency_key = uuid::Uuid::new_v4();
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -34,6 +42,18 @@ pub async fn publish_newsletter_form(
                     >
                 </label>
                 <br>
+                <label>Markdown newsletter:<br>
+                    <textarea
+                        name="markdown_content"
+                        rows="25"
+                        cols="65"
+                    ></textarea>
+                </label>
+                <br>
+                <p>The HTML and plain text versions are generated from the Markdown
+                newsletter. Fill them in only to override what gets generated.</p>
+                <p>Each subscriber's copy can be personalised with {{{{ name }}}},
+                {{{{ subscribed_at }}}} and {{{{ unsubscribe_url }}}}.</p>
                 <label>HTML newsletter:<br>
                     <textarea
                         name="html_content"
@@ -52,8 +72,15 @@ pub async fn publish_newsletter_form(
                     </textarea>
                 </label>
                 <br>
+                {target_lists_html}
+                {audience_filter_html}
+                <label>Schedule for (UTC, leave empty to send right away):<br>
+                    <input type="datetime-local" name="scheduled_for">
+                </label>
+                <br>
                 <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
                 <button type="submit">Publish</button>
+                <button type="submit" formaction="/admin/newsletters/drafts">Save as draft</button>
             </form>
             <p><a href="/admin/dashboard">&lt; Back</a></p>
         </body>
@@ -61,3 +88,38 @@ pub async fn publish_newsletter_form(
         "#,
         )))
 }
+
+/// One checkbox per mailing list, with the default list ticked.
+pub(super) fn target_lists_html(lists: &[MailingList]) -> String {
+    let mut checkboxes_html = String::new();
+    for list in lists {
+        writeln!(
+            checkboxes_html,
+            r#"<label><input type="checkbox" name="list_id" value="{list_id}"{checked}> {name}</label><br>"#,
+            list_id = list.list_id,
+            checked = if list.is_default { " checked" } else { "" },
+            name = encode_minimal(&list.name),
+        )
+        .unwrap();
+    }
+    format!(
+        r#"<fieldset>
+                    <legend>Send to:</legend>
+                    {checkboxes_html}
+                </fieldset>"#
+    )
+}
+
+/// A text input narrowing the target lists down to the subscribers matching
+/// an `AudienceFilter`.
+pub(super) fn audience_filter_html() -> &'static str {
+    r#"<label>Only send to subscribers matching (leave empty to send to everyone):<br>
+                    <input
+                        type="text"
+                        placeholder="tag:beta and subscribed_within:30d"
+                        name="audience_filter"
+                    >
+                </label>
+                <p>Combine <code>tag:&lt;tag&gt;</code> and
+                <code>subscribed_within:&lt;n&gt;d</code> with and, or, not and parentheses.</p>"#
+}
@@ -1,7 +1,13 @@
 //! src/routes/admin/newsletters/mod.rs
 
+mod drafts;
 mod get;
 mod post;
+mod scheduled;
+mod status;
 
+pub use drafts::*;
 pub use get::*;
 pub use post::*;
+pub use scheduled::*;
+pub use status::*;
@@ -1,14 +1,26 @@
 //! src/routes/admin/newsletters/post.rs
 
 use crate::authentication::UserId;
-use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
-use crate::routes::error_chain_fmt;
-use crate::utils::{e400, e500, see_other};
+use crate::configuration::IdempotencySettings;
+use crate::domain::AudienceFilter;
+use crate::idempotency::{
+    save_response, try_processing, IdempotencyKey, NextAction, RequestFingerprint,
+};
+use crate::issue_delivery_worker::{DeliveryStatus, NEW_DELIVERIES_CHANNEL};
+use crate::markdown;
+use crate::routes::{ensure_in_the_future, error_chain_fmt, parse_scheduled_time};
+use crate::sanitize;
+use crate::template::{Template, TemplateError};
+use crate::utils::{e400, e422, e500, see_other};
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
 use anyhow::Context;
-use sqlx::{PgPool, Postgres, Transaction};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::HashSet;
 use uuid::Uuid;
 
 #[derive(thiserror::Error)]
@@ -42,6 +54,15 @@ pub struct NewsletterFormData {
     text_content: String,
     html_content: String,
     idempotency_key: String,
+    /// Left empty to send the issue right away.
+    scheduled_for: Option<String>,
+    /// Generates whichever of `html_content` and `text_content` is left empty.
+    markdown_content: Option<String>,
+    /// One `list_id` field per target list; the default list if there are none.
+    #[serde(default, rename = "list_id")]
+    list_ids: Vec<Uuid>,
+    /// Left empty to send the issue to every member of the target lists.
+    audience_filter: Option<String>,
 }
 
 #[tracing::instrument(
@@ -50,9 +71,10 @@ pub struct NewsletterFormData {
     fields(user_id=%&*user_id)
 )]
 pub async fn publish_newsletter(
-    form: web::Form<NewsletterFormData>,
+    form: UrlEncodedForm<NewsletterFormData>,
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    idempotency_settings: web::Data<IdempotencySettings>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let NewsletterFormData {
@@ -60,51 +82,189 @@ pub async fn publish_newsletter(
         text_content,
         html_content,
         idempotency_key,
+        scheduled_for,
+        markdown_content,
+        list_ids,
+        audience_filter,
     } = form.0;
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
-    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
-        .await
-        .map_err(e500)?
+    let audience_filter = audience_filter.filter(|s| !s.trim().is_empty());
+    let mut fingerprint_parts = vec![title.as_str(), &text_content, &html_content];
+    // Only scheduled issues have a fourth part, so that fingerprints saved
+    // before scheduling existed still match.
+    if let Some(raw) = scheduled_for
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
     {
-        NextAction::StartProcessing(t) => t,
+        fingerprint_parts.push(raw);
+    }
+    if let Some(markdown) = markdown_content.as_deref().filter(|s| !s.trim().is_empty()) {
+        fingerprint_parts.extend(["markdown_content", markdown]);
+    }
+    let list_id_parts: Vec<_> = list_ids.iter().map(Uuid::to_string).collect();
+    if !list_id_parts.is_empty() {
+        fingerprint_parts.push("list_id");
+        fingerprint_parts.extend(list_id_parts.iter().map(String::as_str));
+    }
+    if let Some(filter) = audience_filter.as_deref() {
+        fingerprint_parts.extend(["audience_filter", filter]);
+    }
+    let request_fingerprint = RequestFingerprint::from_parts(&fingerprint_parts);
+    // A retry after the scheduled time has passed must still get the saved
+    // response: only new requests have to be scheduled in the future.
+    let scheduled_for = parse_scheduled_time(scheduled_for.as_deref()).map_err(e400)?;
+    let parsed_audience_filter =
+        AudienceFilter::parse(audience_filter.as_deref().unwrap_or_default()).map_err(e400)?;
+    let (html_content, text_content) =
+        bodies_from_markdown(markdown_content.as_deref(), html_content, text_content);
+    let (html_content, was_sanitized) = sanitize_html_content(html_content);
+    validate_templates(&html_content, &text_content).map_err(e400)?;
+    let mut transaction = match try_processing(
+        &pool,
+        &idempotency_key,
+        *user_id,
+        &request_fingerprint,
+        &idempotency_settings,
+    )
+    .await
+    .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => {
+            ensure_in_the_future(scheduled_for).map_err(e400)?;
+            t
+        }
         NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
+            success_message(scheduled_for).send();
+            if was_sanitized {
+                sanitized_warning().send();
+            }
             return Ok(saved_response);
         }
+        NextAction::RequestInProgress => {
+            let retry_after = idempotency_settings.in_flight_timeout().as_secs().max(1);
+            return Ok(HttpResponse::Conflict()
+                .insert_header((RETRY_AFTER, retry_after))
+                .finish());
+        }
+        NextAction::RejectMismatchedRequest => {
+            return Err(e422(
+                "The idempotency key has already been used for a different request.",
+            ));
+        }
     };
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        scheduled_for,
+        audience_filter.as_deref(),
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
+    let all_lists_exist = set_target_lists(&mut transaction, issue_id, &list_ids)
         .await
-        .context("Failed to store newsletter issue details")
+        .context("Failed to store the target lists of a newsletter issue")
         .map_err(e500)?;
+    if !all_lists_exist {
+        return Err(e400(unknown_list_message()));
+    }
 
-    enqueue_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .context("Failed to enqueue delievery tasks")
-        .map_err(e500)?;
+    enqueue_delivery_tasks(
+        &mut transaction,
+        issue_id,
+        scheduled_for,
+        parsed_audience_filter.as_ref(),
+    )
+    .await
+    .context("Failed to enqueue delievery tasks")
+    .map_err(e500)?;
 
     let response = see_other("/admin/newsletters");
     let response = save_response(transaction, &idempotency_key, *user_id, response)
         .await
         .map_err(e500)?;
 
-    success_message().send();
+    success_message(scheduled_for).send();
+    if was_sanitized {
+        sanitized_warning().send();
+    }
     Ok(response)
     // Ok(HttpResponse::Ok().finish())
 }
 
-fn success_message() -> FlashMessage {
-    FlashMessage::info(
-        "The newsletter issue has been accepted - \
-        emails will go out shortly.",
+/// Renders the bodies that were left empty from the Markdown source, if there
+/// is one. Bodies written by hand are kept as they are.
+pub(super) fn bodies_from_markdown(
+    markdown_content: Option<&str>,
+    html_content: String,
+    text_content: String,
+) -> (String, String) {
+    let markdown_content = match markdown_content.filter(|s| !s.trim().is_empty()) {
+        Some(markdown_content) => markdown_content,
+        None => return (html_content, text_content),
+    };
+    let html_content = if html_content.trim().is_empty() {
+        markdown::to_html(markdown_content)
+    } else {
+        html_content
+    };
+    let text_content = if text_content.trim().is_empty() {
+        markdown::to_text(markdown_content)
+    } else {
+        text_content
+    };
+    (html_content, text_content)
+}
+
+/// Runs the HTML body through the sanitizer, telling whether anything had to
+/// be changed.
+pub(super) fn sanitize_html_content(html_content: String) -> (String, bool) {
+    let sanitized = sanitize::html(&html_content);
+    let was_sanitized = sanitized != html_content;
+    (sanitized, was_sanitized)
+}
+
+pub(super) fn sanitized_warning() -> FlashMessage {
+    FlashMessage::warning(
+        "The HTML newsletter has been changed to remove markup that is not \
+        allowed in emails, such as scripts, event handlers or forms.",
     )
 }
 
+/// Rejects bodies with placeholders that the delivery worker could not render.
+pub(super) fn validate_templates(
+    html_content: &str,
+    text_content: &str,
+) -> Result<(), TemplateError> {
+    Template::parse(html_content)?;
+    Template::parse(text_content)?;
+    Ok(())
+}
+
+pub(super) fn success_message(scheduled_for: Option<DateTime<Utc>>) -> FlashMessage {
+    match scheduled_for {
+        Some(scheduled_for) => FlashMessage::info(format!(
+            "The newsletter issue has been scheduled for {}.",
+            scheduled_for.to_rfc3339()
+        )),
+        None => FlashMessage::info(
+            "The newsletter issue has been accepted - \
+            emails will go out shortly.",
+        ),
+    }
+}
+
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
     title: &str,
     text_content: &str,
     html_content: &str,
+    scheduled_for: Option<DateTime<Utc>>,
+    audience_filter: Option<&str>,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -114,38 +274,165 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
-        )   
-        VALUES ($1, $2, $3, $4, now())
+            published_at,
+            scheduled_for,
+            audience_filter
+        )
+        VALUES ($1, $2, $3, $4, now(), $5, $6)
         "#,
         newsletter_issue_id,
         title,
         text_content,
-        html_content
+        html_content,
+        scheduled_for,
+        audience_filter,
     )
     .execute(transaction)
     .await?;
     Ok(newsletter_issue_id)
 }
 
+/// Sends the issue to `list_ids`, or to the default list if there are none.
+/// Returns `false` if one of the lists does not exist.
+#[tracing::instrument(skip(transaction))]
+pub(super) async fn set_target_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id
+        FROM lists
+        WHERE list_id = ANY($2) OR (cardinality($2) = 0 AND is_default)
+        "#,
+        newsletter_issue_id,
+        list_ids,
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected();
+    let n_requested = list_ids.iter().collect::<HashSet<_>>().len().max(1);
+    Ok(n_inserted == n_requested as u64)
+}
+
+pub(super) fn unknown_list_message() -> &'static str {
+    "One of the target lists does not exist."
+}
+
+/// Enqueues a delivery for every confirmed member of the target lists of the
+/// issue who matches `audience_filter`.
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub(super) async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    scheduled_for: Option<DateTime<Utc>>,
+    audience_filter: Option<&AudienceFilter>,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    let mut query = QueryBuilder::new(
         r#"
         INSERT INTO issue_delivery_queue (
             newsletter_issue_id,
-            subscriber_email
+            subscriber_email,
+            execute_after
         )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        SELECT DISTINCT il.newsletter_issue_id, s.email, COALESCE("#,
+    );
+    query.push_bind(scheduled_for);
+    query.push(
+        r#", now())
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        JOIN newsletter_issue_lists il ON il.list_id = m.list_id
+        WHERE
+            il.newsletter_issue_id = "#,
+    );
+    query.push_bind(newsletter_issue_id);
+    query.push(
+        r#"
+        AND s.status = 'confirmed'
+        AND m.status = 'confirmed'"#,
+    );
+    if let Some(audience_filter) = audience_filter {
+        query.push("\n        AND ");
+        push_audience_filter(&mut query, audience_filter);
+    }
+    query.build().execute(&mut *transaction).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            updated_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, $2, now()
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
         "#,
         newsletter_issue_id,
+        DeliveryStatus::Pending.as_str()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    if scheduled_for.is_some() {
+        // Nothing is due yet: the workers will find the tasks when polling.
+        return Ok(());
+    }
+    // Postgres holds the notification back until the transaction commits, so
+    // workers never wake up before they can see the new tasks.
+    sqlx::query!(
+        r#"SELECT pg_notify($1, $2)"#,
+        NEW_DELIVERIES_CHANNEL,
+        newsletter_issue_id.to_string()
     )
     .execute(transaction)
     .await?;
     Ok(())
 }
+
+/// Compiles a filter into a boolean SQL expression on the subscriber `s`.
+/// Every value is bound as a parameter.
+fn push_audience_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &AudienceFilter) {
+    match filter {
+        AudienceFilter::Tag(tag) => {
+            query.push(
+                "EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = ",
+            );
+            query.push_bind(tag.as_ref().to_string());
+            query.push(")");
+        }
+        AudienceFilter::SubscribedWithinDays(days) => {
+            query.push("s.subscribed_at >= now() - make_interval(days => ");
+            query.push_bind(*days);
+            query.push(")");
+        }
+        AudienceFilter::Not(filter) => {
+            query.push("NOT (");
+            push_audience_filter(query, filter);
+            query.push(")");
+        }
+        AudienceFilter::And(left, right) => push_operation(query, left, " AND ", right),
+        AudienceFilter::Or(left, right) => push_operation(query, left, " OR ", right),
+    }
+}
+
+fn push_operation(
+    query: &mut QueryBuilder<'_, Postgres>,
+    left: &AudienceFilter,
+    operator: &str,
+    right: &AudienceFilter,
+) {
+    query.push("(");
+    push_audience_filter(query, left);
+    query.push(operator);
+    push_audience_filter(query, right);
+    query.push(")");
+}
@@ -0,0 +1,313 @@
+//! src/routes/admin/newsletters/scheduled.rs
+
+use crate::issue_delivery_worker::DeliveryStatus;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use htmlescape::encode_minimal;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Parses the `scheduled_for` field of the admin forms, which must lie in the
+/// future.
+///
+/// Accepts RFC 3339 timestamps as well as the zone-less values sent by
+/// `<input type="datetime-local">`, which are read as UTC. An empty field
+/// means "send now".
+pub fn parse_scheduled_for(value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let scheduled_for = parse_scheduled_time(value)?;
+    ensure_in_the_future(scheduled_for)?;
+    Ok(scheduled_for)
+}
+
+/// Same as [`parse_scheduled_for`], for callers that check the time is in
+/// the future later on, if at all.
+pub fn parse_scheduled_time(value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let value = match value.map(str::trim) {
+        None | Some("") => return Ok(None),
+        Some(value) => value,
+    };
+    let scheduled_for = DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+                .map(|t| Utc.from_utc_datetime(&t))
+        })
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+                .map(|t| Utc.from_utc_datetime(&t))
+        })
+        .map_err(|_| format!("{} is not a valid date and time.", value))?;
+    Ok(Some(scheduled_for))
+}
+
+pub fn ensure_in_the_future(scheduled_for: Option<DateTime<Utc>>) -> Result<(), String> {
+    match scheduled_for {
+        Some(scheduled_for) if scheduled_for <= Utc::now() => {
+            Err("The scheduled time must be in the future.".into())
+        }
+        _ => Ok(()),
+    }
+}
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    scheduled_for: DateTime<Utc>,
+}
+
+pub async fn scheduled_newsletters_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let issues = get_scheduled_issues(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for issue in &issues {
+        writeln!(
+            rows_html,
+            r#"
+                <tr>
+                    <td><a href="/admin/newsletters/{issue_id}">{title}</a></td>
+                    <td>{scheduled_for}</td>
+                    <td>
+                        <form action="/admin/newsletters/scheduled/reschedule" method="post">
+                            <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                            <input type="datetime-local" name="scheduled_for" value="{local_value}">
+                            <button type="submit">Reschedule</button>
+                        </form>
+                    </td>
+                    <td>
+                        <form action="/admin/newsletters/scheduled/cancel" method="post">
+                            <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                            <button type="submit">Cancel</button>
+                        </form>
+                    </td>
+                </tr>"#,
+            issue_id = issue.newsletter_issue_id,
+            title = encode_minimal(&issue.title),
+            scheduled_for = issue.scheduled_for.to_rfc3339(),
+            local_value = issue.scheduled_for.format("%Y-%m-%dT%H:%M"),
+        )
+        .unwrap();
+    }
+    let n_scheduled = issues.len();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Scheduled newsletters</title>
+        </head>
+        <body>
+            {msg_html}
+            <p>{n_scheduled} scheduled issues. Times are in UTC.</p>
+            <table>
+                <tr>
+                    <th>Issue</th>
+                    <th>Scheduled for</th>
+                    <th></th>
+                    <th></th>
+                </tr>
+                {rows_html}
+            </table>
+            <p><a href="/admin/dashboard">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            scheduled_for as "scheduled_for!"
+        FROM newsletter_issues
+        WHERE scheduled_for > now() AND cancelled_at IS NULL
+        ORDER BY scheduled_for
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve scheduled newsletter issues.")?;
+    Ok(rows)
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleFormData {
+    newsletter_issue_id: Uuid,
+    scheduled_for: String,
+}
+
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %form.newsletter_issue_id)
+)]
+pub async fn reschedule_newsletter(
+    form: web::Form<RescheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let scheduled_for = match parse_scheduled_for(Some(&form.scheduled_for)) {
+        Ok(Some(scheduled_for)) => scheduled_for,
+        Ok(None) => {
+            FlashMessage::error("Please pick a new date and time.").send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let n_rescheduled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE
+            newsletter_issue_id = $1
+        AND scheduled_for > now()
+        AND cancelled_at IS NULL
+        "#,
+        form.newsletter_issue_id,
+        scheduled_for,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to reschedule a newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_rescheduled == 0 {
+        FlashMessage::error(not_scheduled_message()).send();
+        return Ok(see_other("/admin/newsletters/scheduled"));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        form.newsletter_issue_id,
+        scheduled_for,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to reschedule the delivery tasks of a newsletter issue.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reschedule a newsletter issue.")
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "The newsletter issue has been rescheduled for {}.",
+        scheduled_for.to_rfc3339()
+    ))
+    .send();
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CancelFormData {
+    newsletter_issue_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %form.newsletter_issue_id)
+)]
+pub async fn cancel_scheduled_newsletter(
+    form: web::Form<CancelFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let n_cancelled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET cancelled_at = now()
+        WHERE
+            newsletter_issue_id = $1
+        AND scheduled_for > now()
+        AND cancelled_at IS NULL
+        "#,
+        form.newsletter_issue_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to cancel a newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_cancelled == 0 {
+        FlashMessage::error(not_scheduled_message()).send();
+        return Ok(see_other("/admin/newsletters/scheduled"));
+    }
+    cancel_delivery_tasks(&mut transaction, form.newsletter_issue_id)
+        .await
+        .context("Failed to cancel the delivery tasks of a newsletter issue.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to cancel a newsletter issue.")
+        .map_err(e500)?;
+    FlashMessage::info("The newsletter issue has been cancelled.").send();
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn cancel_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_deliveries
+        SET
+            status = $2,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1
+        AND status = $3
+        "#,
+        newsletter_issue_id,
+        DeliveryStatus::Cancelled.as_str(),
+        DeliveryStatus::Pending.as_str(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+fn not_scheduled_message() -> &'static str {
+    "The newsletter issue is no longer scheduled: it has already gone out or has been cancelled."
+}
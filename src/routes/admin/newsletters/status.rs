@@ -0,0 +1,135 @@
+//! src/routes/admin/newsletters/status.rs
+
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct RecipientStatus {
+    subscriber_email: String,
+    status: String,
+    updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Show newsletter issue delivery status", skip(pool))]
+pub async fn newsletter_issue_status(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let title = match get_issue_title(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(title) => title,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let recipients = get_recipient_statuses(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+
+    let total = recipients.len();
+    let count = |status: &str| recipients.iter().filter(|r| r.status == status).count();
+    let (n_sent, n_pending, n_failed, n_skipped, n_cancelled) = (
+        count("sent"),
+        count("pending"),
+        count("failed"),
+        count("skipped"),
+        count("cancelled"),
+    );
+    let percent_complete = ((n_sent + n_failed + n_skipped + n_cancelled) * 100)
+        .checked_div(total)
+        .unwrap_or(100);
+
+    let mut rows_html = String::new();
+    for recipient in &recipients {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&recipient.subscriber_email),
+            recipient.status,
+            recipient.updated_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    let title = encode_minimal(&title);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>{title}</title>
+        </head>
+        <body>
+            <h1>{title}</h1>
+            <ul>
+                <li>Recipients: {total}</li>
+                <li>Sent: {n_sent}</li>
+                <li>Pending: {n_pending}</li>
+                <li>Failed: {n_failed}</li>
+                <li>Skipped: {n_skipped}</li>
+                <li>Cancelled: {n_cancelled}</li>
+                <li>Complete: {percent_complete}%</li>
+            </ul>
+            <table>
+                <tr>
+                    <th>Recipient</th>
+                    <th>Status</th>
+                    <th>Updated at</th>
+                </tr>
+                {rows_html}
+            </table>
+            <p><a href="/admin/dashboard">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_title(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT title
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue.")?;
+    Ok(row.map(|r| r.title))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_recipient_statuses(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<RecipientStatus>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        RecipientStatus,
+        r#"
+        SELECT subscriber_email, status, updated_at
+        FROM newsletter_issue_deliveries
+        WHERE newsletter_issue_id = $1
+        ORDER BY subscriber_email
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the delivery status of a newsletter issue.")?;
+    Ok(rows)
+}
@@ -0,0 +1,188 @@
+//! src/routes/admin/tags.rs
+
+use crate::domain::{SubscriberEmail, SubscriberTag};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+struct TagSummary {
+    tag: String,
+    n_subscribers: i64,
+}
+
+pub async fn tags_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let tags = get_tag_summaries(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for tag in &tags {
+        writeln!(
+            rows_html,
+            r#"
+                <tr>
+                    <td>{tag}</td>
+                    <td>{n_subscribers}</td>
+                </tr>"#,
+            tag = encode_minimal(&tag.tag),
+            n_subscribers = tag.n_subscribers,
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Subscriber tags</title>
+        </head>
+        <body>
+            {msg_html}
+            <table>
+                <tr>
+                    <th>Tag</th>
+                    <th>Subscribers</th>
+                </tr>
+                {rows_html}
+            </table>
+            <form action="/admin/subscribers/tags" method="post">
+                <label>Email:
+                    <input type="text" name="email" placeholder="Enter the subscriber's email">
+                </label>
+                <label>Tag:
+                    <input type="text" name="tag" placeholder="Enter the tag">
+                </label>
+                <button type="submit">Tag</button>
+                <button type="submit" formaction="/admin/subscribers/tags/remove">Untag</button>
+            </form>
+            <p><a href="/admin/dashboard">&lt; Back</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct TagFormData {
+    email: String,
+    tag: String,
+}
+
+#[tracing::instrument(name = "Tag a subscriber", skip(pool))]
+pub async fn tag_subscriber(
+    form: web::Form<TagFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (email, tag) = match parse_form(form.0) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/subscribers/tags"));
+        }
+    };
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT id, $2
+        FROM subscriptions
+        WHERE email = $1
+        ON CONFLICT DO NOTHING
+        "#,
+        email.as_ref(),
+        tag.as_ref(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to tag a subscriber.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted == 0 && !subscriber_exists(&pool, &email).await.map_err(e500)? {
+        FlashMessage::error("There is no subscriber with this email.").send();
+    } else {
+        FlashMessage::info("The subscriber has been tagged.").send();
+    }
+    Ok(see_other("/admin/subscribers/tags"))
+}
+
+#[tracing::instrument(name = "Untag a subscriber", skip(pool))]
+pub async fn untag_subscriber(
+    form: web::Form<TagFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (email, tag) = match parse_form(form.0) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/subscribers/tags"));
+        }
+    };
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM subscriber_tags
+        USING subscriptions
+        WHERE subscriber_tags.subscriber_id = subscriptions.id
+            AND subscriptions.email = $1
+            AND subscriber_tags.tag = $2
+        "#,
+        email.as_ref(),
+        tag.as_ref(),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to untag a subscriber.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_deleted == 0 {
+        FlashMessage::error("This subscriber does not have this tag.").send();
+    } else {
+        FlashMessage::info("The tag has been removed.").send();
+    }
+    Ok(see_other("/admin/subscribers/tags"))
+}
+
+fn parse_form(form: TagFormData) -> Result<(SubscriberEmail, SubscriberTag), String> {
+    let email = SubscriberEmail::parse(form.email)?;
+    let tag = SubscriberTag::parse(form.tag)?;
+    Ok((email, tag))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn subscriber_exists(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE email = $1) as "exists!""#,
+        email.as_ref(),
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up a subscriber.")?;
+    Ok(row.exists)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_tag_summaries(pool: &PgPool) -> Result<Vec<TagSummary>, anyhow::Error> {
+    let tags = sqlx::query_as!(
+        TagSummary,
+        r#"
+        SELECT tag, count(*) as "n_subscribers!"
+        FROM subscriber_tags
+        GROUP BY tag
+        ORDER BY tag
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscriber tags.")?;
+    Ok(tags)
+}
@@ -0,0 +1,160 @@
+//! src/routes/archive.rs
+
+use crate::sanitize;
+use crate::template::{Recipient, Template};
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct ArchivedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    sent_at: DateTime<Utc>,
+}
+
+/// Lists the issues that have gone out, most recent first. Drafts, cancelled
+/// issues and issues scheduled in the future stay private, and so do issues
+/// sent to other lists than the default one or to a filtered audience.
+pub async fn newsletter_archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_archived_issues(&pool).await.map_err(e500)?;
+    let mut items_html = String::new();
+    for issue in &issues {
+        writeln!(
+            items_html,
+            r#"<li>{sent_on} - <a href="/newsletters/{issue_id}">{title}</a></li>"#,
+            sent_on = issue.sent_at.format("%Y-%m-%d"),
+            issue_id = issue.newsletter_issue_id,
+            title = encode_minimal(&issue.title),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Newsletter archive</title>
+        </head>
+        <body>
+            <h1>Past issues</h1>
+            <ul>
+                {items_html}
+            </ul>
+            <p><a href="/">&lt; Home</a></p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+#[tracing::instrument(name = "Show an archived newsletter issue", skip(pool))]
+pub async fn archived_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            title,
+            html_content,
+            COALESCE(scheduled_for, published_at) as "sent_at!"
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        AND published_at IS NOT NULL
+        AND cancelled_at IS NULL
+        AND COALESCE(scheduled_for, published_at) <= now()
+        AND audience_filter IS NULL
+        AND NOT EXISTS (
+            SELECT 1
+            FROM newsletter_issue_lists il
+            JOIN lists l ON l.list_id = il.list_id
+            WHERE il.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+            AND NOT l.is_default
+        )
+        "#,
+        *newsletter_issue_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve an archived newsletter issue.")
+    .map_err(e500)?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let title = encode_minimal(&issue.title);
+    // Public readers are nobody in particular and have nothing to unsubscribe
+    // from.
+    let reader = Recipient {
+        name: "",
+        unsubscribe_url: "",
+        subscribed_at: issue.sent_at,
+    };
+    // Issues published before newsletters were sanitized are stored as they
+    // were written.
+    let html_content = sanitize::html(&issue.html_content);
+    let html_content = Template::parse(&html_content)
+        .unwrap_or_else(|_| Template::verbatim(&html_content))
+        .render_html(&reader);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>{title}</title>
+        </head>
+        <body>
+            <h1>{title}</h1>
+            <p>{sent_on}</p>
+            {html_content}
+            <p><a href="/newsletters">&lt; All issues</a></p>
+        </body>
+        </html>
+        "#,
+            sent_on = issue.sent_at.format("%Y-%m-%d"),
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_archived_issues(pool: &PgPool) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            COALESCE(scheduled_for, published_at) as "sent_at!"
+        FROM newsletter_issues
+        WHERE
+            published_at IS NOT NULL
+        AND cancelled_at IS NULL
+        AND COALESCE(scheduled_for, published_at) <= now()
+        AND audience_filter IS NULL
+        AND NOT EXISTS (
+            SELECT 1
+            FROM newsletter_issue_lists il
+            JOIN lists l ON l.list_id = il.list_id
+            WHERE il.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+            AND NOT l.is_default
+        )
+        ORDER BY 3 DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve archived newsletter issues.")?;
+    Ok(issues)
+}
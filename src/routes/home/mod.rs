@@ -1,9 +1,51 @@
 //! scr/routes/home/mod.rs
 
-use actix_web::{http::header::ContentType, HttpResponse};
+use crate::routes::get_lists;
+use crate::utils::e500;
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
+pub async fn home(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    let mut options_html = String::new();
+    for list in &lists {
+        writeln!(
+            options_html,
+            r#"<option value="{list_id}"{selected}>{name}</option>"#,
+            list_id = list.list_id,
+            selected = if list.is_default { " selected" } else { "" },
+            name = encode_minimal(&list.name),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Home</title>
+  </head>
+  <body>
+    <p>Welcome to our newsletter!</p>
+    <form action="/subscriptions" method="post">
+      <label>Name: <input type="text" name="name"></label>
+      <label>Email: <input type="email" name="email"></label>
+      <label>List:
+        <select name="list_id">
+          {options_html}
+        </select>
+      </label>
+      <button type="submit">Subscribe</button>
+    </form>
+    <p><a href="/newsletters">Read past issues</a></p>
+    <a href="/login">login</a>
+  </body>
+</html>
+"#,
+        )))
 }
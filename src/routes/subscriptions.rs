@@ -2,7 +2,7 @@
 
 use crate::{
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    email_client::{EmailClient, SendEmailError},
     startup::ApplicationBaseUrl,
 };
 use actix_web::http::StatusCode;
@@ -19,6 +19,8 @@ use uuid::Uuid;
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Left out to subscribe to the default list.
+    pub list_id: Option<Uuid>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -97,18 +99,74 @@ pub async fn subscribe(
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into()?;
+    let list_id = form.list_id;
+    let new_subscriber: NewSubscriber = form.0.try_into()?;
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool.")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let list_id = get_list_id(&mut transaction, list_id)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| {
+            SubscribeError::ValidationError("The mailing list does not exist.".into())
+        })?;
+    // Inserting first, rather than looking the address up, settles concurrent
+    // subscriptions of a new address: the losers wait for the winner to commit
+    // and then find its row.
+    let inserted_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    // The name an existing subscriber asks for is only applied once the
+    // address is confirmed again, not by whoever fills in the form.
+    let mut pending_name = None;
+    let subscriber_id = match inserted_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let subscriber = get_subscriber_by_email(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look up an existing subscriber in the database.")?
+                .context("The existing subscriber could not be found.")?;
+            match subscriber.status.as_str() {
+                // Same response as for a brand new subscriber: we must not
+                // reveal who is already on the list. Addresses that bounced or
+                // complained must not get any further email from us.
+                "bounced" | "complained" => return Ok(HttpResponse::Ok().finish()),
+                // A confirmed address only has to confirm lists it is not on yet.
+                "confirmed" => {
+                    let membership_status =
+                        get_membership_status(&mut transaction, list_id, subscriber.id)
+                            .await
+                            .context("Failed to look up an existing list membership.")?;
+                    if membership_status.as_deref() == Some("confirmed") {
+                        return Ok(HttpResponse::Ok().finish());
+                    }
+                }
+                _ => {
+                    mark_subscriber_as_pending(&mut transaction, subscriber.id)
+                        .await
+                        .context(
+                            "Failed to reset an existing subscriber to pending confirmation.",
+                        )?;
+                }
+            }
+            pending_name = Some(new_subscriber.name.as_ref());
+            subscriber.id
+        }
+    };
+    add_pending_membership(&mut transaction, list_id, subscriber_id)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to add the subscriber to the mailing list.")?;
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list_id,
+        pending_name,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
     transaction
         .commit()
         .await
@@ -120,23 +178,52 @@ pub async fn subscribe(
         &subscription_token,
     )
     .await
-    .context("Failed to send a confirmtaion email.")?;
+    .map_err(|e| match e {
+        SendEmailError::InvalidRecipient(_) => {
+            SubscribeError::ValidationError("The email address cannot receive emails.".into())
+        }
+        e => SubscribeError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to send a confirmtaion email."),
+        ),
+    })?;
     Ok(HttpResponse::Ok().finish())
 }
 
+/// `subscriber_name` replaces the name of the subscriber when the token is
+/// used to confirm. Earlier unused tokens for the same list stop working, so
+/// that only the latest confirmation email can confirm.
 #[tracing::instrument(
     name = "Store subscription token in the database",
-    skip(subscription_token, transaction)
+    skip(subscriber_name, subscription_token, transaction)
 )]
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
+    subscriber_name: Option<&str>,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscriber_id, subscription_token)
-        VALUES ($1, $2)"#,
+        r#"
+        UPDATE subscription_tokens
+        SET used_at = now()
+        WHERE subscriber_id = $1 AND list_id = $2 AND used_at IS NULL
+        "#,
         subscriber_id,
+        list_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(StoreTokenError)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens
+            (subscriber_id, list_id, subscriber_name, subscription_token)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        subscriber_id,
+        list_id,
+        subscriber_name,
         subscription_token,
     )
     .execute(transaction)
@@ -154,7 +241,7 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -174,6 +261,7 @@ pub async fn send_confirmation_email(
         .await
 }
 
+/// Returns `None`, and changes nothing, if the email is already in use.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -181,24 +269,161 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(subscriber.map(|s| s.id))
+}
+
+pub struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(
+    name = "Looking up an existing subscriber by email",
+    skip(email, transaction)
+)]
+pub async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        SELECT id, status
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Resetting an existing subscriber to pending confirmation",
+    skip(transaction)
+)]
+pub async fn mark_subscriber_as_pending(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation'
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub name: String,
+    pub is_default: bool,
+}
+
+#[tracing::instrument(name = "Get all mailing lists", skip(pool))]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, name, is_default
+        FROM lists
+        ORDER BY is_default DESC, name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Resolves the list a form asked for, falling back to the default list.
+/// Returns `None` if the list does not exist.
+#[tracing::instrument(name = "Look up a mailing list", skip(transaction))]
+pub async fn get_list_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Option<Uuid>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let list = sqlx::query!(
+        r#"
+        SELECT list_id
+        FROM lists
+        WHERE list_id = $1 OR ($1 IS NULL AND is_default)
+        "#,
+        list_id,
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(list.map(|l| l.list_id))
+}
+
+#[tracing::instrument(
+    name = "Looking up the membership of a subscriber in a list",
+    skip(transaction)
+)]
+pub async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let membership = sqlx::query!(
+        r#"
+        SELECT status
+        FROM list_memberships
+        WHERE list_id = $1 AND subscriber_id = $2
+        FOR UPDATE
+        "#,
+        list_id,
+        subscriber_id,
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(membership.map(|m| m.status))
+}
+
+#[tracing::instrument(
+    name = "Adding a subscriber to a list, pending confirmation",
+    skip(transaction)
+)]
+pub async fn add_pending_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status)
+        VALUES ($1, $2, 'pending_confirmation')
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = 'pending_confirmation'
+        "#,
+        list_id,
+        subscriber_id,
+    )
     .execute(transaction)
     .await?;
-    Ok(subscriber_id)
+    Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,8 +1,19 @@
 //! src/routes/subscriptions_confirm.rs
 
-use actix_web::web;
-use actix_web::HttpResponse;
-use sqlx::PgPool;
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::routes::{
+    error_chain_fmt, generate_subscription_token, send_confirmation_email, store_token,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -10,59 +21,336 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            };
-            HttpResponse::Ok().finish()
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("The subscription token is unknown.")]
+    UnknownToken,
+    #[error("The subscription token has expired or has already been used.")]
+    StaleToken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::StaleToken(_) => StatusCode::GONE,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmError::StaleToken(subscription_token) => stale_token_page(subscription_token),
+            _ => HttpResponse::new(self.status_code()),
         }
     }
 }
 
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool, settings)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, ConfirmError> {
+    let cutoff = token_expiry_cutoff(settings.confirmation_token_ttl())?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let token = get_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscription token.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if token.used_at.is_some() {
+        // Following the same link twice is harmless, but a used token must
+        // not confirm a subscriber who has since left the list again.
+        return if token.subscription_status == "confirmed" {
+            Ok(confirmed_page())
+        } else {
+            Err(ConfirmError::StaleToken(parameters.0.subscription_token))
+        };
+    }
+    // Only a subscription waiting for this confirmation can be confirmed: an
+    // address that has since unsubscribed, bounced or complained stays out.
+    if token.created_at <= cutoff || token.subscription_status != "pending_confirmation" {
+        return Err(ConfirmError::StaleToken(parameters.0.subscription_token));
+    }
+    mark_token_as_used(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to mark the subscription token as used.")?;
+    confirm_subscriber(
+        &mut transaction,
+        token.subscriber_id,
+        token.list_id,
+        token.subscriber_name.as_deref(),
+    )
+    .await
+    .context("Failed to mark the subscriber as confirmed.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(confirmed_page())
+}
+
+/// Tokens issued before the returned instant are expired.
+fn token_expiry_cutoff(ttl: std::time::Duration) -> Result<DateTime<Utc>, anyhow::Error> {
+    Ok(Utc::now() - chrono::Duration::from_std(ttl)?)
+}
+
+fn confirmed_page() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Subscription confirmed</title>
+        </head>
+        <body>
+            <p>Thank you, your subscription is confirmed!</p>
+        </body>
+        </html>
+        "#,
+    )
+}
+
+/// Served for tokens that exist but are expired or already used, so that the
+/// subscriber can ask for a fresh confirmation email.
+fn stale_token_page(subscription_token: &str) -> HttpResponse {
+    let subscription_token = htmlescape::encode_attribute(subscription_token);
+    HttpResponse::Gone()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Confirmation link expired</title>
+        </head>
+        <body>
+            <p>This confirmation link has expired or has already been used.</p>
+            <form action="/subscriptions/confirm/resend" method="post">
+                <input type="hidden" name="subscription_token" value="{subscription_token}">
+                <button type="submit">Send me a new confirmation email</button>
+            </form>
+        </body>
+        </html>
+        "#,
+        ))
+}
+
+struct StoredToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    subscriber_name: Option<String>,
+    created_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    /// The status of the membership of the list, unless the address itself
+    /// has left or cannot receive emails anymore.
+    subscription_status: String,
+}
+
+/// Locks the token row, so that concurrent clicks on the same link are
+/// processed one after the other.
+#[tracing::instrument(
+    name = "Get subscription token"
+    skip(subscription_token, transaction)
+)]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT
+            t.subscriber_id,
+            t.list_id,
+            t.subscriber_name,
+            t.created_at,
+            t.used_at,
+            CASE WHEN s.status IN ('pending_confirmation', 'confirmed')
+                THEN m.status
+                ELSE s.status
+            END AS "subscription_status!"
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        JOIN list_memberships m ON m.subscriber_id = t.subscriber_id AND m.list_id = t.list_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE OF t
+        "#,
+        subscription_token,
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Mark subscription token as used"
+    skip(subscription_token, transaction)
+)]
+async fn mark_token_as_used(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscription_tokens SET used_at = now() WHERE subscription_token = $1"#,
+        subscription_token,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Confirms both the address and its membership of the list, and applies
+/// the name given when subscribing again, if any.
 #[tracing::instrument(
     name = "Mark subscriber as confirmed"
-    skip(subscriber_id, pool)
+    skip(subscriber_id, subscriber_name, transaction)
 )]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    subscriber_name: Option<&str>,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"
+        UPDATE subscriptions
+        SET status = 'confirmed', name = COALESCE($2, name)
+        WHERE id = $1 AND status IN ('pending_confirmation', 'confirmed')
+        "#,
         subscriber_id,
+        subscriber_name,
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND list_id = $2 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(transaction)
+    .await?;
     Ok(())
 }
 
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    subscription_token: String,
+}
+
+/// Issues a new token, for the same list, to the owner of an expired or used
+/// one. Only subscribers still waiting for confirmation get an email.
 #[tracing::instrument(
-    name = "Get subscriber_id from token"
-    skip(subscription_token, pool)
+    name = "Resend a confirmation email",
+    skip(form, pool, email_client, base_url)
 )]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
-    subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
-        subscription_token,
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            t.list_id,
+            t.subscriber_name,
+            CASE WHEN s.status IN ('pending_confirmation', 'confirmed')
+                THEN m.status
+                ELSE s.status
+            END AS "status!"
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        JOIN list_memberships m ON m.subscriber_id = t.subscriber_id AND m.list_id = t.list_id
+        WHERE t.subscription_token = $1
+        "#,
+        form.subscription_token,
     )
-    .fetch_optional(pool)
+    .fetch_optional(pool.get_ref())
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
+    .context("Failed to look up the subscriber owning a subscription token.")
+    .map_err(e500)?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+    let message = if subscriber.status == "pending_confirmation" {
+        let new_subscriber = NewSubscriber {
+            email: SubscriberEmail::parse(subscriber.email).map_err(e500)?,
+            name: SubscriberName::parse(subscriber.name).map_err(e500)?,
+        };
+        let subscription_token = generate_subscription_token();
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")
+            .map_err(e500)?;
+        store_token(
+            &mut transaction,
+            subscriber.id,
+            subscriber.list_id,
+            subscriber.subscriber_name.as_deref(),
+            &subscription_token,
+        )
+        .await
+        .context("Failed to store a new confirmation token.")
+        .map_err(e500)?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store a new confirmation token.")
+            .map_err(e500)?;
+        send_confirmation_email(
+            &email_client,
+            new_subscriber,
+            &base_url.0,
+            &subscription_token,
+        )
+        .await
+        .context("Failed to send a confirmation email.")
+        .map_err(e500)?;
+        "A new confirmation email is on its way."
+    } else if subscriber.status == "confirmed" {
+        "Your subscription is already confirmed."
+    } else {
+        "There is no subscription waiting for confirmation. Please subscribe again."
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Confirmation email</title>
+        </head>
+        <body>
+            <p>{message}</p>
+        </body>
+        </html>
+        "#,
+        )))
 }
@@ -0,0 +1,172 @@
+//! src/routes/subscriptions_unsubscribe.rs
+
+use crate::domain::{UnsubscribeToken, Unsubscription};
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Landing page for the link in the email body: it only asks for a
+/// confirmation, so that link scanners following it do not unsubscribe anyone.
+#[tracing::instrument(name = "Show the unsubscribe form", skip_all)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let unsubscription = UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let list_name = list_name(&pool, &unsubscription).await?;
+    let token = htmlescape::encode_attribute(&parameters.token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Unsubscribe</title>
+        </head>
+        <body>
+            <form action="/subscriptions/unsubscribe?token={token}" method="post">
+                <p>Do you want to stop receiving {list_name}?</p>
+                <button type="submit">Unsubscribe</button>
+            </form>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+/// Target of both the form above and RFC 8058 one-click unsubscribe requests.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip_all)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let unsubscription = UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    mark_subscriber_as_unsubscribed(&pool, &unsubscription)
+        .await
+        .context("Failed to unsubscribe a subscriber.")?;
+    let list_name = list_name(&pool, &unsubscription).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Unsubscribed</title>
+        </head>
+        <body>
+            <p>You have been unsubscribed. You will not receive {list_name} anymore.</p>
+        </body>
+        </html>
+        "#,
+        )))
+}
+
+/// How the pages above refer to what is being left.
+async fn list_name(
+    pool: &PgPool,
+    unsubscription: &Unsubscription,
+) -> Result<String, UnsubscribeError> {
+    let list_id = match unsubscription.list_id {
+        Some(list_id) => list_id,
+        None => return Ok("our newsletters".into()),
+    };
+    let list = sqlx::query!(r#"SELECT name FROM lists WHERE list_id = $1"#, list_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up a mailing list.")?;
+    match list {
+        Some(list) => Ok(format!(
+            "the {} mailing list",
+            htmlescape::encode_minimal(&list.name)
+        )),
+        None => Err(UnsubscribeError::InvalidToken(anyhow::anyhow!(
+            "The mailing list does not exist."
+        ))),
+    }
+}
+
+/// Leaves the list named by the token, or every list for tokens that do not
+/// name one. An address that is left without any list, confirmed or still
+/// awaiting confirmation, is unsubscribed altogether.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    unsubscription: &Unsubscription,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'unsubscribed'
+        WHERE
+            subscriber_id = $1
+        AND (list_id = $2 OR $2 IS NULL)
+        "#,
+        unsubscription.subscriber_id,
+        unsubscription.list_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed'
+        WHERE
+            id = $1
+        -- Addresses that bounced or complained must stay out for good
+        AND status IN ('pending_confirmation', 'confirmed')
+        AND NOT EXISTS (
+            SELECT 1
+            FROM list_memberships
+            WHERE
+                subscriber_id = $1
+            AND status IN ('pending_confirmation', 'confirmed')
+        )
+        "#,
+        unsubscription.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! src/routes/webhooks.rs
+
+use crate::configuration::WebhookSettings;
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+
+/// The fields we need from Postmark's bounce and spam complaint webhooks.
+/// Every other record type is acknowledged and ignored.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkEvent {
+    record_type: String,
+    email: Option<String>,
+    #[serde(rename = "Type")]
+    bounce_type: Option<String>,
+    #[serde(default)]
+    inactive: bool,
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("The webhook payload is not a valid Postmark event.")]
+    InvalidPayload(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            WebhookError::InvalidPayload(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Process a Postmark webhook",
+    skip_all,
+    fields(
+        record_type = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty
+    )
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    // The body is only parsed once the caller is known to be Postmark.
+    check_credentials(request.headers(), &settings).map_err(WebhookError::AuthError)?;
+    let event: PostmarkEvent =
+        serde_json::from_slice(&body).map_err(WebhookError::InvalidPayload)?;
+    tracing::Span::current().record("record_type", tracing::field::display(&event.record_type));
+    let status = match event.record_type.as_str() {
+        "SpamComplaint" => "complained",
+        "Bounce" if is_hard_bounce(&event) => "bounced",
+        _ => return Ok(HttpResponse::Ok().finish()),
+    };
+    let email = event
+        .email
+        .as_deref()
+        .context("The event does not mention an email address.")?;
+    tracing::Span::current().record("subscriber_email", tracing::field::display(email));
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE email = $1"#,
+        email,
+        status
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the status of a subscriber.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Soft bounces (full mailbox, temporary DNS issue...) are left alone: only
+/// addresses that will never accept our emails are flagged.
+fn is_hard_bounce(event: &PostmarkEvent) -> bool {
+    event.inactive
+        || matches!(
+            event.bounce_type.as_deref(),
+            Some("HardBounce" | "BadEmailAddress")
+        )
+}
+
+fn check_credentials(headers: &HeaderMap, settings: &WebhookSettings) -> Result<(), anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("The 'Basic' credentials are not in the 'username:password' format.")?;
+    let password = Secret::new(password.to_string());
+    // Both parts are always compared, in constant time, so that the response
+    // time tells nothing about the credentials.
+    let is_valid = username.as_bytes().ct_eq(settings.username.as_bytes())
+        & password
+            .expose_secret()
+            .as_bytes()
+            .ct_eq(settings.password.expose_secret().as_bytes());
+    if !bool::from(is_valid) {
+        anyhow::bail!("Invalid webhook credentials.");
+    }
+    Ok(())
+}
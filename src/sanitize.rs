@@ -0,0 +1,74 @@
+//! src/sanitize.rs
+
+/// Keeps only the tags and attributes of an allowlist that is safe to send to
+/// inboxes and to show in the archive: scripts, event handlers, forms and
+/// `javascript:` links are dropped.
+///
+/// Many email clients drop style sheets, so inline styles and the presentational
+/// attributes of table layouts are kept.
+///
+/// Links are left without a `rel` of our own, so that sanitizing HTML that was
+/// already sanitized (for instance, rendered from Markdown) changes nothing.
+pub fn html(unsafe_html: &str) -> String {
+    ammonia::Builder::default()
+        .link_rel(None)
+        .add_generic_attributes([
+            "rel",
+            "style",
+            "dir",
+            "align",
+            "valign",
+            "bgcolor",
+            "width",
+            "height",
+            "border",
+            "cellpadding",
+            "cellspacing",
+        ])
+        .add_tags(["font"])
+        .add_tag_attributes("font", ["color", "face", "size"])
+        .clean(unsafe_html)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html;
+
+    #[test]
+    fn scripts_event_handlers_and_forms_are_removed() {
+        let clean = html(
+            r#"<p onclick="steal()">Hi</p><script>alert(1)</script><form action="https://evil.example"><input name="password"></form>"#,
+        );
+
+        assert_eq!(clean, "<p>Hi</p>");
+    }
+
+    #[test]
+    fn javascript_links_are_removed() {
+        let clean = html(r#"<a href="javascript:alert(1)">Click</a>"#);
+
+        assert_eq!(clean, "<a>Click</a>");
+    }
+
+    #[test]
+    fn safe_html_is_left_as_it_is() {
+        let safe = r#"<h1>Title</h1><p>Some <em>text</em> and <a href="https://example.com" rel="noopener noreferrer">a link</a>.</p><ul><li>one</li></ul>"#;
+
+        assert_eq!(html(safe), safe);
+    }
+
+    #[test]
+    fn styled_tables_are_left_as_they_are() {
+        let safe = r##"<table width="600" cellpadding="0" cellspacing="0" border="0" align="center" bgcolor="#ffffff" style="border-collapse: collapse;"><tbody><tr><td valign="top" style="padding: 16px; font-family: Arial, sans-serif; color: #333333;"><font color="#ff0000" face="Arial">Hello</font></td></tr></tbody></table>"##;
+
+        assert_eq!(html(safe), safe);
+    }
+
+    #[test]
+    fn placeholders_survive_sanitization() {
+        let safe = r#"<p>Hi {{ name }}, <a href="{{ unsubscribe_url }}">unsubscribe</a></p>"#;
+
+        assert_eq!(html(safe), safe);
+    }
+}
@@ -0,0 +1,40 @@
+//! src/shutdown.rs
+
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Creates a shutdown signal. Background workers hold (clones of) the
+/// `ShutdownSignal`, `main` keeps the sender and flips it to `true` once the
+/// process has been asked to stop.
+pub fn channel() -> (watch::Sender<bool>, ShutdownSignal) {
+    let (sender, receiver) = watch::channel(false);
+    (sender, ShutdownSignal(receiver))
+}
+
+#[derive(Clone, Debug)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// A dropped sender counts as a shutdown request: nobody is left to
+    /// send one otherwise.
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow() || self.0.has_changed().is_err()
+    }
+
+    /// Resolves as soon as shutdown has been requested.
+    pub async fn requested(&mut self) {
+        while !self.is_requested() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Sleeps for `duration`, waking up early if shutdown is requested.
+    pub async fn sleep(&mut self, duration: Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = self.requested() => {}
+        }
+    }
+}
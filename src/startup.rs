@@ -2,11 +2,21 @@
 
 use crate::authentication::reject_anonymous_users;
 use crate::configuration::DatabaseSettings;
+use crate::configuration::IdempotencySettings;
 use crate::configuration::Settings;
+use crate::configuration::SubscriptionSettings;
+use crate::configuration::WebhookSettings;
+use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, publish_newsletter_form, subscribe,
+    admin_dashboard, archived_issue, cancel_scheduled_newsletter, change_password,
+    change_password_form, confirm, create_draft, create_list, drafts_page, edit_draft_form,
+    failed_deliveries_page, health_check, home, lists_page, log_out, login, login_form,
+    newsletter_archive, newsletter_issue_status, postmark_webhook, preview_draft, publish_draft,
+    publish_newsletter, publish_newsletter_form, requeue_all_failed_deliveries,
+    requeue_failed_delivery, reschedule_newsletter, resend_confirmation, save_draft,
+    scheduled_newsletters_page, send_test_draft, subscribe, tag_subscriber, tags_page, unsubscribe,
+    unsubscribe_form, untag_subscriber,
 };
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
@@ -31,7 +41,14 @@ pub struct Application {
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        configuration
+            .webhooks
+            .validate(&configuration.environment)?;
         let connection_pool = get_connection_pool(&configuration.database);
+        let admin_email = configuration
+            .email_client
+            .admin()
+            .expect("Invalid admin email address.");
         let email_client = configuration.email_client.client();
         let address = format!(
             "{}:{}",
@@ -43,9 +60,13 @@ impl Application {
             listener,
             connection_pool,
             email_client,
+            admin_email,
             configuration.application.base_url,
             configuration.application.hmac_secret,
             configuration.redis_uri,
+            configuration.idempotency,
+            configuration.subscriptions,
+            configuration.webhooks,
         )
         .await?;
 
@@ -63,6 +84,9 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// Where the test copies of newsletter drafts are sent.
+pub struct AdminEmail(pub SubscriberEmail);
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
@@ -72,18 +96,27 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 #[derive(Debug)]
 pub struct HmacSecret(pub Secret<String>);
 
+#[allow(clippy::too_many_arguments)]
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
+    admin_email: SubscriberEmail,
     base_url: String,
     hmac_secret: Secret<String>,
     redis_uri: Secret<String>,
+    idempotency_settings: IdempotencySettings,
+    subscription_settings: SubscriptionSettings,
+    webhook_settings: WebhookSettings,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let admin_email = Data::new(AdminEmail(admin_email));
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let idempotency_settings = Data::new(idempotency_settings);
+    let subscription_settings = Data::new(subscription_settings);
+    let webhook_settings = Data::new(webhook_settings);
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
@@ -102,6 +135,58 @@ async fn run(
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/newsletters", web::post().to(publish_newsletter))
                     .route("/newsletters", web::get().to(publish_newsletter_form))
+                    .route("/newsletters/drafts", web::get().to(drafts_page))
+                    .route("/newsletters/drafts", web::post().to(create_draft))
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}",
+                        web::get().to(edit_draft_form),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}",
+                        web::post().to(save_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}/preview",
+                        web::get().to(preview_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}/test",
+                        web::post().to(send_test_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{newsletter_issue_id}/publish",
+                        web::post().to(publish_draft),
+                    )
+                    .route(
+                        "/newsletters/scheduled",
+                        web::get().to(scheduled_newsletters_page),
+                    )
+                    .route(
+                        "/newsletters/scheduled/reschedule",
+                        web::post().to(reschedule_newsletter),
+                    )
+                    .route(
+                        "/newsletters/scheduled/cancel",
+                        web::post().to(cancel_scheduled_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(newsletter_issue_status),
+                    )
+                    .route("/deliveries/failed", web::get().to(failed_deliveries_page))
+                    .route(
+                        "/deliveries/failed/requeue",
+                        web::post().to(requeue_failed_delivery),
+                    )
+                    .route(
+                        "/deliveries/failed/requeue_all",
+                        web::post().to(requeue_all_failed_deliveries),
+                    )
+                    .route("/lists", web::get().to(lists_page))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/subscribers/tags", web::get().to(tags_page))
+                    .route("/subscribers/tags", web::post().to(tag_subscriber))
+                    .route("/subscribers/tags/remove", web::post().to(untag_subscriber))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(log_out)),
@@ -109,12 +194,31 @@ async fn run(
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .route("/health_check", web::get().to(health_check))
+            .route("/newsletters", web::get().to(newsletter_archive))
+            .route(
+                "/newsletters/{newsletter_issue_id}",
+                web::get().to(archived_issue),
+            )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
             .app_data(Data::new(HmacSecret(hmac_secret.clone())))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(admin_email.clone())
             .app_data(base_url.clone())
+            .app_data(idempotency_settings.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(webhook_settings.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,179 @@
+//! src/template.rs
+//!
+//! Newsletter bodies can be personalised with placeholders such as
+//! `{{ name }}`. The syntax is deliberately tiny: a placeholder is one of a
+//! fixed set of variables between double braces, with no logic and no way to
+//! reach anything but the recipient's own subscription.
+
+use chrono::{DateTime, Utc};
+use htmlescape::encode_attribute;
+
+#[derive(Debug, PartialEq)]
+enum Variable {
+    Name,
+    UnsubscribeUrl,
+    SubscribedAt,
+}
+
+impl Variable {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "name" => Some(Self::Name),
+            "unsubscribe_url" => Some(Self::UnsubscribeUrl),
+            "subscribed_at" => Some(Self::SubscribedAt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error(
+        "`{{{{ {0} }}}}` is not a known placeholder. \
+        Use {{{{ name }}}}, {{{{ unsubscribe_url }}}} or {{{{ subscribed_at }}}}."
+    )]
+    UnknownPlaceholder(String),
+    #[error("A placeholder is missing its closing `}}}}`.")]
+    Unclosed,
+}
+
+/// What a template can say about the subscriber it is rendered for.
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// A newsletter body, parsed once and rendered for every recipient.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .map(|end| start + end)
+                .ok_or(TemplateError::Unclosed)?;
+            let name = rest[start + 2..end].trim();
+            let variable = Variable::parse(name)
+                .ok_or_else(|| TemplateError::UnknownPlaceholder(name.to_string()))?;
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            parts.push(Part::Variable(variable));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// A template that renders `source` as it is, placeholders included.
+    pub fn verbatim(source: &str) -> Self {
+        Self {
+            parts: vec![Part::Literal(source.to_string())],
+        }
+    }
+
+    /// Renders an HTML body. Values are escaped so that they are safe both in
+    /// text and inside attributes.
+    pub fn render_html(&self, recipient: &Recipient) -> String {
+        self.render(recipient, encode_attribute)
+    }
+
+    pub fn render_text(&self, recipient: &Recipient) -> String {
+        self.render(recipient, |value| value.to_string())
+    }
+
+    fn render(&self, recipient: &Recipient, escape: impl Fn(&str) -> String) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Variable(Variable::Name) => rendered.push_str(&escape(recipient.name)),
+                Part::Variable(Variable::UnsubscribeUrl) => {
+                    rendered.push_str(&escape(recipient.unsubscribe_url))
+                }
+                Part::Variable(Variable::SubscribedAt) => {
+                    let subscribed_at = recipient.subscribed_at.format("%B %-d, %Y").to_string();
+                    rendered.push_str(&escape(&subscribed_at))
+                }
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recipient, Template, TemplateError};
+    use chrono::{TimeZone, Utc};
+
+    fn recipient() -> Recipient<'static> {
+        Recipient {
+            name: "Ada <Lovelace>",
+            unsubscribe_url: "https://example.com/unsubscribe?token=a&b",
+            subscribed_at: Utc.with_ymd_and_hms(2023, 4, 29, 6, 42, 47).unwrap(),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_replaced_in_text() {
+        let template =
+            Template::parse("Hi {{name}}, since {{ subscribed_at }}: {{ unsubscribe_url }}")
+                .unwrap();
+
+        assert_eq!(
+            template.render_text(&recipient()),
+            "Hi Ada <Lovelace>, since April 29, 2023: https://example.com/unsubscribe?token=a&b"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let template = Template::parse("<p>Hi {{ name }}</p>").unwrap();
+
+        assert_eq!(
+            template.render_html(&recipient()),
+            "<p>Hi Ada&#x20;&lt;Lovelace&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn a_body_without_placeholders_is_rendered_as_it_is() {
+        let body = "<p>Nothing { to } see here</p>";
+
+        assert_eq!(
+            Template::parse(body).unwrap().render_html(&recipient()),
+            body
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_eq!(
+            Template::parse("Hi {{ email }}").unwrap_err(),
+            TemplateError::UnknownPlaceholder("email".into())
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert_eq!(
+            Template::parse("Hi {{ name").unwrap_err(),
+            TemplateError::Unclosed
+        );
+    }
+}
@@ -18,6 +18,13 @@ where
     actix_web::error::ErrorBadRequest(e)
 }
 
+pub fn e422<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorUnprocessableEntity(e)
+}
+
 pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .status(StatusCode::SEE_OTHER)
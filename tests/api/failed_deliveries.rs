@@ -0,0 +1,185 @@
+//! tests/api/failed_deliveries.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, publish_newsletter, spawn_app,
+    when_sending_a_batch, PostmarkBatchResponder, TestApp,
+};
+use wiremock::ResponseTemplate;
+
+async fn create_failed_delivery(app: &mut TestApp) -> String {
+    app.issue_delivery_settings.max_retries = 0;
+    create_confirmed_subscriber(app).await;
+    app.test_user.login(app).await;
+    publish_newsletter(app).await;
+
+    let _mock_guard = when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT subscriber_email FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscriber_email
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_failed_deliveries().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_listed_with_their_error() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let email = create_failed_delivery(&mut app).await;
+
+    // Act
+    let html_page = app.get_failed_deliveries_html().await;
+
+    // Assert
+    assert!(html_page.contains(&email));
+    assert!(html_page.contains("500 Internal Server Error"));
+}
+
+#[tokio::test]
+async fn invalid_stored_emails_are_recorded_as_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    sqlx::query!("UPDATE subscriptions SET email = 'not-an-email'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE issue_delivery_queue SET subscriber_email = 'not-an-email'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let failed = sqlx::query!("SELECT subscriber_email, error_chain FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.subscriber_email, "not-an-email");
+    assert!(failed
+        .error_chain
+        .contains("is not a valid subscriber email"));
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_failed_delivery_can_be_requeued() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let email = create_failed_delivery(&mut app).await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act - Part 1 - Requeue the delivery
+    let response = app
+        .post_requeue_failed_delivery(&serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "subscriber_email": email,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/deliveries/failed");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The delivery to {} has been requeued.</i></p>",
+        email
+    )));
+    assert!(html_page.contains("0 failed deliveries."));
+
+    // Act - Part 3 - The worker delivers it this time
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn all_failed_deliveries_can_be_requeued_at_once() {
+    // Arrange
+    let mut app = spawn_app().await;
+    create_failed_delivery(&mut app).await;
+
+    // Act
+    let response = app.post_requeue_all_failed_deliveries().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/deliveries/failed");
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains("<p><i>1 failed deliveries have been requeued.</i></p>"));
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_failed_delivery_that_is_already_queued_is_kept() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let email = create_failed_delivery(&mut app).await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    sqlx::query!(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) VALUES ($1, $2)",
+        newsletter_issue_id,
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.post_requeue_failed_delivery(&serde_json::json!({
+        "newsletter_issue_id": newsletter_issue_id,
+        "subscriber_email": email,
+    }))
+    .await;
+
+    // Assert
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains("The delivery could not be found or is already queued."));
+    let n_failed = sqlx::query!(r#"SELECT count(*) as "count!" FROM failed_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_failed, 1);
+}
@@ -0,0 +1,353 @@
+//! tests/api/mailing_lists.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, batch_messages, create_confirmed_subscriber, spawn_app,
+    when_sending_a_batch, ConfirmationLinks, PostmarkBatchResponder, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Creates a list through the admin page and returns its id.
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let response = app.post_lists(&serde_json::json!({ "name": name })).await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    sqlx::query!("SELECT list_id FROM lists WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+/// Subscribes the same address to `list_id` and returns the confirmation links.
+async fn subscribe_to(app: &TestApp, list_id: Uuid) -> ConfirmationLinks {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={}",
+        list_id
+    );
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).await
+}
+
+async fn confirm(links: ConfirmationLinks) {
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Publishes an issue to `list_id` and returns the unsubscribe link it was
+/// sent with.
+async fn unsubscribe_link_for(app: &TestApp, list_id: Uuid) -> reqwest::Url {
+    let _mock_guard = when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "list_id": list_id,
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let message = batch_messages(&email_request).pop().unwrap();
+    app.get_unsubscribe_link(&message)
+}
+
+async fn membership_status(app: &TestApp, list_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM list_memberships WHERE list_id = $1",
+        list_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_lists(&serde_json::json!({ "name": "Rust" })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_admin_can_create_lists_with_unique_names() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Create
+    create_list(&app, "Rust").await;
+
+    // Assert - Part 1
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The list has been created."));
+    assert!(html_page.contains("Rust"));
+    assert!(html_page.contains("Newsletter (default)"));
+
+    // Act - Part 2 - Create again
+    app.post_lists(&serde_json::json!({ "name": "Rust" })).await;
+
+    // Assert - Part 2
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("A list with this name already exists."));
+}
+
+#[tokio::test]
+async fn the_subscribe_form_offers_every_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let list_id = create_list(&app, "Rust").await;
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(&app.address)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains(&format!(r#"<option value="{}">Rust</option>"#, list_id)));
+}
+
+#[tokio::test]
+async fn subscribing_to_a_list_only_confirms_that_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let list_id = create_list(&app, "Rust").await;
+
+    // Act
+    let links = subscribe_to(&app, list_id).await;
+    assert_eq!(
+        membership_status(&app, list_id).await,
+        "pending_confirmation"
+    );
+    confirm(links).await;
+
+    // Assert
+    assert_eq!(membership_status(&app, list_id).await, "confirmed");
+    let n_memberships = sqlx::query!(r#"SELECT count(*) as "count!" FROM list_memberships"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_memberships, 1);
+}
+
+#[tokio::test]
+async fn a_confirmed_subscriber_must_confirm_each_new_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let rust_id = create_list(&app, "Rust").await;
+    let go_id = create_list(&app, "Go").await;
+    confirm(subscribe_to(&app, rust_id).await).await;
+
+    // Act
+    let links = subscribe_to(&app, go_id).await;
+
+    // Assert
+    assert_eq!(membership_status(&app, go_id).await, "pending_confirmation");
+    confirm(links).await;
+    assert_eq!(membership_status(&app, go_id).await, "confirmed");
+    assert_eq!(membership_status(&app, rust_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={}",
+        Uuid::new_v4()
+    );
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_members_of_their_target_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    // Subscribed to the default list only.
+    create_confirmed_subscriber(&app).await;
+    let list_id = create_list(&app, "Rust").await;
+    confirm(subscribe_to(&app, list_id).await).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p> Newsletter body as HTML </p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "list_id": list_id,
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let messages = batch_messages(&email_request);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["To"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p> Newsletter body as HTML </p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "list_id": Uuid::new_v4(),
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn unsubscribing_leaves_only_the_list_of_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let rust_id = create_list(&app, "Rust").await;
+    let go_id = create_list(&app, "Go").await;
+    confirm(subscribe_to(&app, rust_id).await).await;
+    confirm(subscribe_to(&app, go_id).await).await;
+    let unsubscribe_link = unsubscribe_link_for(&app, rust_id).await;
+
+    // Act
+    let form_page = reqwest::get(unsubscribe_link.clone())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(form_page.contains("Do you want to stop receiving the Rust mailing list?"));
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(membership_status(&app, rust_id).await, "unsubscribed");
+    assert_eq!(membership_status(&app, go_id).await, "confirmed");
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_pending_list_can_still_be_confirmed_after_leaving_the_only_confirmed_one() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let rust_id = create_list(&app, "Rust").await;
+    let go_id = create_list(&app, "Go").await;
+    let rust_links = subscribe_to(&app, rust_id).await;
+    let go_links = subscribe_to(&app, go_id).await;
+    confirm(rust_links).await;
+    let unsubscribe_link = unsubscribe_link_for(&app, rust_id).await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(go_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(membership_status(&app, rust_id).await, "unsubscribed");
+    assert_eq!(membership_status(&app, go_id).await, "confirmed");
+}
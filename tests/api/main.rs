@@ -2,9 +2,17 @@
 
 mod admin_dashboard;
 mod change_password;
+mod failed_deliveries;
 mod health_check;
 mod helpers;
 mod login;
+mod mailing_lists;
+mod newsletter_archive;
+mod newsletter_drafts;
 mod newsletters;
+mod scheduled_newsletters;
+mod subscriber_tags;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod webhooks;
@@ -0,0 +1,227 @@
+//! tests/api/newsletter_archive.rs
+
+use crate::helpers::{publish_newsletter, spawn_app, TestApp};
+use uuid::Uuid;
+
+/// Publishes an issue and returns its id.
+async fn publish_issue(app: &TestApp, title: &str, extra_fields: serde_json::Value) -> Uuid {
+    let mut newsletter_form = serde_json::json!({
+        "title": title,
+        "text_content": "Newsletter body as plain text",
+        "html_content": format!("<p>The body of {}</p>", title),
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    newsletter_form
+        .as_object_mut()
+        .unwrap()
+        .extend(extra_fields.as_object().unwrap().clone());
+    app.post_newsletters(&newsletter_form).await;
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn the_archive_is_public() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    app.post_logout().await;
+
+    // Act
+    let response = app.get_archive("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Newsletter title"));
+}
+
+#[tokio::test]
+async fn only_issues_that_went_out_are_archived() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let sent = publish_issue(&app, "Sent issue", serde_json::json!({})).await;
+    let scheduled = publish_issue(
+        &app,
+        "Scheduled issue",
+        serde_json::json!({"scheduled_for": "2099-01-01T09:30"}),
+    )
+    .await;
+    app.post_drafts(&serde_json::json!({
+        "title": "Draft issue",
+        "text_content": "Draft body",
+        "html_content": "<p>Draft body</p>",
+    }))
+    .await;
+
+    // Act
+    let html_page = app.get_archive("").await.text().await.unwrap();
+
+    // Assert
+    assert!(html_page.contains(&format!(
+        r#"<a href="/newsletters/{}">Sent issue</a>"#,
+        sent
+    )));
+    assert!(!html_page.contains("Scheduled issue"));
+    assert!(!html_page.contains("Draft issue"));
+    let response = app.get_archive(&format!("/{}", scheduled)).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn archived_issues_are_listed_most_recent_first() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let older = publish_issue(&app, "Older issue", serde_json::json!({})).await;
+    publish_issue(&app, "Newer issue", serde_json::json!({})).await;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = '2020-05-17T08:00:00Z' WHERE newsletter_issue_id = $1",
+        older
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let html_page = app.get_archive("").await.text().await.unwrap();
+
+    // Assert
+    let newer_position = html_page.find("Newer issue").unwrap();
+    let older_position = html_page.find("2020-05-17 - ").unwrap();
+    assert!(newer_position < older_position);
+}
+
+#[tokio::test]
+async fn an_archived_issue_renders_its_html_content() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app, "Sent issue", serde_json::json!({})).await;
+
+    // Act
+    let response = app.get_archive(&format!("/{}", issue_id)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Sent issue</h1>"));
+    assert!(html_page.contains("<p>The body of Sent issue</p>"));
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_archive(&format!("/{}", Uuid::new_v4())).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn issues_sent_to_other_lists_or_to_a_segment_are_not_archived() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_lists(&serde_json::json!({ "name": "Insiders" }))
+        .await;
+    let list_id = sqlx::query!("SELECT list_id FROM lists WHERE name = 'Insiders'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id;
+    let list_only = publish_issue(
+        &app,
+        "List-only issue",
+        serde_json::json!({ "list_id": list_id }),
+    )
+    .await;
+    let segment_only = publish_issue(
+        &app,
+        "Segment-only issue",
+        serde_json::json!({ "audience_filter": "tag:beta" }),
+    )
+    .await;
+    publish_issue(&app, "Public issue", serde_json::json!({})).await;
+    app.post_logout().await;
+
+    // Act
+    let html_page = app.get_archive("").await.text().await.unwrap();
+
+    // Assert
+    assert!(html_page.contains("Public issue"));
+    assert!(!html_page.contains("List-only issue"));
+    assert!(!html_page.contains("Segment-only issue"));
+    for issue_id in [list_only, segment_only] {
+        let response = app.get_archive(&format!("/{}", issue_id)).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn archived_issues_render_placeholders_for_nobody_in_particular() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Personal issue",
+        "text_content": "Hi {{ name }}",
+        "html_content": r#"<p>Hi {{ name }}!</p><p><a href="{{ unsubscribe_url }}">Leave</a></p>"#,
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act
+    let html_page = app
+        .get_archive(&format!("/{}", issue_id))
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("<p>Hi !</p>"));
+    assert!(!html_page.contains("{{"));
+}
+
+#[tokio::test]
+async fn archived_issues_stored_before_sanitization_are_sanitized() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app, "Old issue", serde_json::json!({})).await;
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET html_content = '<p onclick="steal()">Hi</p><script>alert(1)</script>'"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let html_page = app
+        .get_archive(&format!("/{}", issue_id))
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("<p>Hi</p>"));
+    assert!(!html_page.contains("<script>"));
+    assert!(!html_page.contains("onclick"));
+}
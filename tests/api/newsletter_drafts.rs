@@ -0,0 +1,462 @@
+//! tests/api/newsletter_drafts.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_a_batch,
+    PostmarkBatchResponder, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Saves a draft through the newsletter form and returns its id.
+async fn save_draft(app: &TestApp) -> Uuid {
+    let response = app
+        .post_drafts(&serde_json::json!({
+            "title": "Draft title",
+            "text_content": "Draft body as plain text",
+            "html_content": "<p>Draft body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "scheduled_for": "",
+        }))
+        .await;
+    let newsletter_issue_id = sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = 'Draft title'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", newsletter_issue_id),
+    );
+    newsletter_issue_id
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_drafts() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_drafts(&serde_json::json!({})).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn saving_a_draft_does_not_send_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_issue_id = save_draft(&app).await;
+
+    // Assert
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("1 drafts."));
+    assert!(html_page.contains(&newsletter_issue_id.to_string()));
+    assert!(html_page.contains("Draft title"));
+}
+
+#[tokio::test]
+async fn a_draft_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    // Act
+    let response = app
+        .post_draft(
+            newsletter_issue_id,
+            "",
+            &serde_json::json!({
+                "title": "New title",
+                "text_content": "New body",
+                "html_content": "<p>New body</p>",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", newsletter_issue_id),
+    );
+    let html_page = app
+        .get_draft(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The draft has been saved."));
+    assert!(html_page.contains(&htmlescape::encode_attribute("New title")));
+    assert!(html_page.contains("&lt;p&gt;New body&lt;/p&gt;"));
+}
+
+#[tokio::test]
+async fn a_draft_can_be_written_in_markdown() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    // Act
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "",
+            "html_content": "",
+            "markdown_content": "Some **Markdown**",
+        }),
+    )
+    .await;
+
+    // Assert
+    let issue = sqlx::query!("SELECT html_content, text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        issue.html_content,
+        "<p>Some <strong>Markdown</strong></p>\n"
+    );
+    assert_eq!(issue.text_content, "Some Markdown");
+}
+
+#[tokio::test]
+async fn a_markdown_draft_can_be_edited_in_markdown() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "",
+            "html_content": "",
+            "markdown_content": "Some **Markdown**",
+        }),
+    )
+    .await;
+
+    // Act - Part 1 - Open the editor
+    let html_page = app
+        .get_draft(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - The Markdown is editable, the bodies generated from it are not
+    assert!(html_page.contains(
+        r#"<textarea name="markdown_content" rows="25" cols="65">Some **Markdown**</textarea>"#
+    ));
+    assert!(html_page.contains(r#"<textarea name="html_content" rows="25" cols="65"></textarea>"#));
+    assert!(html_page.contains(r#"<textarea name="text_content" rows="25" cols="65"></textarea>"#));
+
+    // Act - Part 2 - Submit the editor with the Markdown changed
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "",
+            "html_content": "",
+            "markdown_content": "Some *edited* Markdown",
+        }),
+    )
+    .await;
+
+    // Assert
+    let issue = sqlx::query!("SELECT html_content, text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.html_content, "<p>Some <em>edited</em> Markdown</p>\n");
+    assert_eq!(issue.text_content, "Some edited Markdown");
+}
+
+#[tokio::test]
+async fn hand_written_bodies_of_a_markdown_draft_are_kept_in_the_editor() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "",
+            "html_content": "<p>Hand written</p>",
+            "markdown_content": "Some **Markdown**",
+        }),
+    )
+    .await;
+
+    // Act
+    let html_page = app
+        .get_draft(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("&lt;p&gt;Hand written&lt;/p&gt;"));
+    assert!(html_page.contains(r#"<textarea name="text_content" rows="25" cols="65"></textarea>"#));
+}
+
+#[tokio::test]
+async fn the_preview_shows_both_bodies_with_the_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+
+    // Act
+    let response = app.get_draft(newsletter_issue_id, "/preview").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let html_body = htmlescape::encode_attribute("<p>Draft body as HTML</p><p><a href=");
+    assert!(html_page.contains(&html_body));
+    assert!(html_page.contains("Draft body as plain text\n\nUnsubscribe: "));
+}
+
+#[tokio::test]
+async fn a_test_email_is_sent_to_the_admin_address_only() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_draft(newsletter_issue_id, "/test", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", newsletter_issue_id),
+    );
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.configuration.email_client.admin_email);
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let html_page = app
+        .get_draft(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("A test email has been sent to"));
+}
+
+#[tokio::test]
+async fn test_email_errors_are_escaped_in_the_error_message() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 300,
+            "Message": "<script>alert(1)</script>",
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_draft(newsletter_issue_id, "/test", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    let html_page = app
+        .get_draft(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The test email could not be sent:"));
+    assert!(html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(!html_page.contains("<script>"));
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_draft(
+            newsletter_issue_id,
+            "/publish",
+            &serde_json::json!({"scheduled_for": ""}),
+        )
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("The newsletter issue has been accepted"));
+    assert!(html_page.contains("0 drafts."));
+}
+
+#[tokio::test]
+async fn a_draft_with_unknown_placeholders_cannot_be_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "Hi {{ nickname }}",
+            "html_content": "<p>Hi</p>",
+        }),
+    )
+    .await;
+
+    // Act
+    let response = app
+        .post_draft(newsletter_issue_id, "/publish", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", newsletter_issue_id),
+    );
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let html_page = app
+        .get_draft(newsletter_issue_id, "")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("is not a known placeholder"));
+}
+
+#[tokio::test]
+async fn unsafe_html_is_removed_when_a_draft_is_published() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "Draft title",
+            "text_content": "Draft body",
+            "html_content": "<p>Draft body</p><script>alert(1)</script>",
+        }),
+    )
+    .await;
+
+    // Act
+    app.post_draft(newsletter_issue_id, "/publish", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    let html_content = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .html_content;
+    assert_eq!(html_content, "<p>Draft body</p>");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("The HTML newsletter has been changed"));
+}
+
+#[tokio::test]
+async fn a_published_draft_can_be_neither_published_again_nor_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = save_draft(&app).await;
+    app.post_draft(newsletter_issue_id, "/publish", &serde_json::json!({}))
+        .await;
+
+    // Act - Part 1 - Publish again
+    app.post_draft(newsletter_issue_id, "/publish", &serde_json::json!({}))
+        .await;
+
+    // Assert - Part 1
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("This issue has already been published"));
+
+    // Act - Part 2 - Edit
+    app.post_draft(
+        newsletter_issue_id,
+        "",
+        &serde_json::json!({
+            "title": "New title",
+            "text_content": "New body",
+            "html_content": "<p>New body</p>",
+        }),
+    )
+    .await;
+
+    // Assert - Part 2
+    let title = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .title;
+    assert_eq!(title, "Draft title");
+    let response = app.get_draft(newsletter_issue_id, "").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
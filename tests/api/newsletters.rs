@@ -2,16 +2,16 @@
 
 use std::time::Duration;
 
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
-use wiremock::matchers::{any, method, path};
-use wiremock::{Mock, MockBuilder, ResponseTemplate};
-
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
-}
+use crate::helpers::{
+    assert_is_redirect_to, batch_messages, create_confirmed_subscriber,
+    create_unconfirmed_subscriber, make_pending_deliveries_due, publish_newsletter, spawn_app,
+    when_sending_a_batch, PostmarkBatchResponder, TestApp,
+};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::idempotency::delete_expired_keys;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::shutdown;
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -51,82 +51,218 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Mock verifies on Drop that the newsletters are not sent
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email
-    }))
-    .unwrap();
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    // Act - Part 1 - Login
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
 
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on drop that we have sent the newsletter email
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_delivered_with_generated_html_and_text() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "",
+        "html_content": "\n    ",
+        "markdown_content": "# Big news\n\nRead [the post](https://example.com/post).",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+    app.dispatch_all_pending_emails().await;
 
-    let email_request = &app
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let email_request = app
         .email_server
         .received_requests()
         .await
         .unwrap()
         .pop()
         .unwrap();
-
-    app.get_confirmation_links(&email_request).await
+    let message = &batch_messages(&email_request)[0];
+    let html_body = message["HtmlBody"].as_str().unwrap();
+    assert!(html_body.starts_with("<h1>Big news</h1>"));
+    assert!(html_body.contains(r#"<a href="https://example.com/post""#));
+    let text_body = message["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with("Big news\n\nRead the post (https://example.com/post).\n\n"));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
+#[tokio::test]
+async fn hand_written_bodies_take_precedence_over_markdown() {
     // Arrange
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "A hand-written text body",
+        "html_content": "",
+        "markdown_content": "A *Markdown* body",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    let issue = sqlx::query!("SELECT html_content, text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
+    assert_eq!(issue.html_content, "<p>A <em>Markdown</em> body</p>\n");
+    assert_eq!(issue.text_content, "A hand-written text body");
 }
 
 #[tokio::test]
-async fn newsletters_are_delivered_to_confirmed_subscribers() {
-    // Act - Part 1 - Login
+async fn placeholders_are_rendered_for_each_recipient() {
+    // Arrange
     let app = spawn_app().await;
     app.test_user.login(&app).await;
     create_confirmed_subscriber(&app).await;
-
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+    let name = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .name;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
         .expect(1)
         .mount(&app.email_server)
         .await;
 
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ name }}, leave at {{ unsubscribe_url }}",
+        "html_content": "<p>Hi {{name}}</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&newsletter_form).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let message = &batch_messages(&email_request)[0];
+    let html_body = message["HtmlBody"].as_str().unwrap();
+    let expected_html = format!("<p>Hi {}</p>", htmlescape::encode_attribute(&name));
+    assert!(html_body.starts_with(&expected_html));
+    let text_body = message["TextBody"].as_str().unwrap();
+    let expected_text = format!(
+        "Hi {}, leave at {}/subscriptions/unsubscribe?",
+        name, app.base_url.0
+    );
+    assert!(text_body.starts_with(&expected_text));
+}
+
+#[tokio::test]
+async fn issues_with_unknown_placeholders_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ email }}",
+        "html_content": "<p>Hi {{ name }</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn unsafe_html_is_removed_on_publish_with_a_warning() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
     // Act
     let newsletter_form = serde_json::json!({
         "title": "Newsletter title",
         "text_content": "Newsletter body as plain text",
-        "html_content": "<p> Newsletter body as HTML </p>",
+        "html_content": r#"<p onmouseover="steal()">Hi</p><script>alert(1)</script><form action="https://evil.example"><input name="password"></form>"#,
         "idempotency_key": uuid::Uuid::new_v4().to_string(),
     });
     let response = app.post_newsletters(&newsletter_form).await;
 
     // Assert
     assert_is_redirect_to(&response, "/admin/newsletters");
-    // Act - Part 3 - Follow the redirect
+    let html_content = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .html_content;
+    assert_eq!(html_content, "<p>Hi</p>");
     let html_page = app.get_newsletters_html().await;
-    assert!(html_page.contains(
-        "<p><i>The newsletter issue has been accepted - \
-        emails will go out shortly.</i></p>"
-    ));
-    app.dispatch_all_pending_emails().await;
-    // Mock verifies on drop that we have sent the newsletter email
+    assert!(html_page.contains("The HTML newsletter has been changed"));
+}
+
+#[tokio::test]
+async fn safe_html_is_published_without_a_warning() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    publish_newsletter(&app).await;
+
+    // Assert
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been accepted"));
+    assert!(!html_page.contains("The HTML newsletter has been changed"));
 }
 
 #[tokio::test]
@@ -214,9 +350,8 @@ async fn newsletter_creation_is_idempotent() {
     .await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -259,9 +394,8 @@ async fn concurrent_form_subission_is_handled_gracefully() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all().delayed_by(Duration::from_secs(2)))
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -287,6 +421,749 @@ async fn concurrent_form_subission_is_handled_gracefully() {
     // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+
+    // Act - Part 1 - The first attempt fails
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - The task is still queued, with a backoff
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.execute_after > chrono::Utc::now());
+
+    // Act - Part 2 - The retry succeeds once the backoff has elapsed
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .named("Delivery retry")
+        .mount(&app.email_server)
+        .await;
+    make_pending_deliveries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_after_max_retries() {
+    // Arrange
+    let mut app = spawn_app().await;
+    app.issue_delivery_settings.max_retries = 2;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - One initial attempt plus two retries
+    for _ in 0..3 {
+        app.dispatch_all_pending_emails().await;
+        make_pending_deliveries_due(&app).await;
+    }
+
+    // Assert
+    let n_queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let failed = sqlx::query!("SELECT n_retries FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.n_retries, 2);
+}
+
+#[tokio::test]
+async fn issue_status_page_reports_delivery_progress() {
+    // Arrange
+    let mut app = spawn_app().await;
+    app.issue_delivery_settings.max_retries = 0;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act - Part 1 - Nothing has been sent yet
+    let html_page = app
+        .get_newsletter_issue_status_html(newsletter_issue_id)
+        .await;
+    assert!(html_page.contains("<li>Pending: 2</li>"));
+    assert!(html_page.contains("<li>Complete: 0%</li>"));
+
+    // Act - Part 2 - One delivery succeeds, the other one is rejected
+    let rejected_email = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::rejecting(vec![rejected_email]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app
+        .get_newsletter_issue_status_html(newsletter_issue_id)
+        .await;
+    assert!(html_page.contains("<li>Sent: 1</li>"));
+    assert!(html_page.contains("<li>Pending: 0</li>"));
+    assert!(html_page.contains("<li>Failed: 1</li>"));
+    assert!(html_page.contains("<li>Complete: 100%</li>"));
+}
+
+#[tokio::test]
+async fn newsletters_are_sent_to_all_recipients_in_a_single_batch() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let messages = batch_messages(&email_request);
+    assert_eq!(messages.len(), 3);
+    assert!(messages.iter().all(|m| m["Subject"] == "Newsletter title"));
+    let n_sent = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM newsletter_issue_deliveries WHERE status = 'sent'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_sent, 3);
+}
+
+#[tokio::test]
+async fn workers_finish_the_queue_and_exit_once_shutdown_is_requested() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    let mut configuration = app.configuration.clone();
+    configuration.issue_delivery.n_workers = 2;
+    configuration.issue_delivery.batch_size = 1;
+    let (shutdown_sender, shutdown) = shutdown::channel();
+    let workers = tokio::spawn(run_worker_until_stopped(configuration, shutdown));
+
+    // Act
+    let n_pending = wait_for_the_queue_to_drain(&app).await;
+    shutdown_sender.send(true).unwrap();
+
+    // Assert
+    assert_eq!(n_pending, 0);
+    // The workers are idle, waiting for the next poll: they must not wait
+    // for the poll interval to elapse before exiting.
+    tokio::time::timeout(Duration::from_secs(2), workers)
+        .await
+        .expect("The workers did not exit after shutdown was requested.")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rate_limited_workers_exit_once_shutdown_is_requested() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7200"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    let mut configuration = app.configuration.clone();
+    configuration.issue_delivery.n_workers = 1;
+    configuration.issue_delivery.batch_size = 1;
+    let (shutdown_sender, shutdown) = shutdown::channel();
+    let workers = tokio::spawn(run_worker_until_stopped(configuration, shutdown));
+
+    // Act - Wait for the 429 to pause the worker for two hours, with the
+    // second delivery still due
+    let mut postponed = false;
+    for _ in 0..50 {
+        postponed = sqlx::query!(
+            r#"SELECT count(*) as "count!" FROM issue_delivery_queue WHERE execute_after > now()"#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+            == 1;
+        if postponed {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(postponed, "The delivery was not postponed.");
+    shutdown_sender.send(true).unwrap();
+
+    // Assert
+    tokio::time::timeout(Duration::from_secs(2), workers)
+        .await
+        .expect("The workers did not exit while waiting for the rate limit.")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn workers_refuse_to_start_with_invalid_batch_sizes_or_no_workers() {
+    let app = spawn_app().await;
+    let invalid_settings: [(i64, usize); 4] = [(0, 1), (-1, 1), (501, 1), (100, 0)];
+    for (batch_size, n_workers) in invalid_settings {
+        let mut configuration = app.configuration.clone();
+        configuration.issue_delivery.batch_size = batch_size;
+        configuration.issue_delivery.n_workers = n_workers;
+        let (_shutdown_sender, shutdown) = shutdown::channel();
+
+        let outcome = tokio::time::timeout(
+            Duration::from_secs(2),
+            run_worker_until_stopped(configuration, shutdown),
+        )
+        .await
+        .expect("The workers started with invalid settings.");
+
+        assert!(
+            outcome.is_err(),
+            "The workers started with batch_size = {} and n_workers = {}.",
+            batch_size,
+            n_workers
+        );
+    }
+}
+
+/// Returns the number of tasks still queued once the queue is empty, or after
+/// five seconds.
+async fn wait_for_the_queue_to_drain(app: &TestApp) -> i64 {
+    let mut n_pending = i64::MAX;
+    for _ in 0..50 {
+        n_pending = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+        if n_pending == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    n_pending
+}
+
+#[tokio::test]
+async fn idle_workers_wake_up_as_soon_as_an_issue_is_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let mut configuration = app.configuration.clone();
+    configuration
+        .issue_delivery
+        .empty_queue_poll_interval_milliseconds = 3_600_000;
+    let (shutdown_sender, shutdown) = shutdown::channel();
+    let workers = tokio::spawn(run_worker_until_stopped(configuration, shutdown));
+    // Publishing before the workers listen would leave them asleep for an hour.
+    for _ in 0..50 {
+        let n_listeners = sqlx::query!(
+            r#"
+            SELECT count(*) as "count!" FROM pg_stat_activity
+            WHERE datname = current_database() AND query LIKE 'LISTEN%'
+            "#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+        if n_listeners > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    // Act
+    publish_newsletter(&app).await;
+    let n_pending = wait_for_the_queue_to_drain(&app).await;
+
+    // Assert
+    assert_eq!(n_pending, 0);
+    shutdown_sender.send(true).unwrap();
+    workers.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn deliveries_are_dequeued_in_chunks_of_batch_size() {
+    // Arrange
+    let mut app = spawn_app().await;
+    app.issue_delivery_settings.batch_size = 2;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let batch_sizes: Vec<_> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .map(|r| batch_messages(r).len())
+        .collect();
+    assert_eq!(batch_sizes, vec![2, 1]);
+}
+
+#[tokio::test]
+async fn inactive_recipients_are_marked_as_bounced_without_retrying() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::rejecting(vec![email]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "bounced");
+    let failed = sqlx::query!("SELECT n_retries, error_chain FROM failed_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.n_retries, 0);
+    assert!(failed.error_chain.contains("marked as inactive"));
+}
+
+#[tokio::test]
+async fn rate_limited_deliveries_are_retried_after_the_requested_delay() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7200"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 0);
+    assert!(task.execute_after > chrono::Utc::now() + chrono::Duration::seconds(7000));
+}
+
+#[tokio::test]
+async fn rejected_credentials_leave_the_queue_untouched() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+            "ErrorCode": 10,
+            "Message": "The Server Token you provided was invalid."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let outcome = app.dispatch_pending_emails_once().await;
+
+    // Assert
+    assert!(outcome.is_err());
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 0);
+    assert!(task.execute_after <= chrono::Utc::now());
+    let n_failed = sqlx::query!(r#"SELECT count(*) as "count!" FROM failed_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_failed, 0);
+}
+
+#[tokio::test]
+async fn deliveries_sent_before_an_account_error_are_settled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 405, "Message": "You have run out of credits." },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let outcome = app.dispatch_pending_emails_once().await;
+
+    // Assert
+    assert!(outcome.is_err());
+    let tasks = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].n_retries, 0);
+    assert!(tasks[0].execute_after <= chrono::Utc::now());
+    let statuses: Vec<_> =
+        sqlx::query!("SELECT status FROM newsletter_issue_deliveries ORDER BY status DESC")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.status)
+            .collect();
+    assert_eq!(statuses, vec!["sent", "pending"]);
+    let n_failed = sqlx::query!(r#"SELECT count(*) as "count!" FROM failed_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_failed, 0);
+}
+
+#[tokio::test]
+async fn workers_pause_while_the_email_provider_rejects_our_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    let mut configuration = app.configuration.clone();
+    configuration.issue_delivery.n_workers = 2;
+    let (shutdown_sender, shutdown) = shutdown::channel();
+    let workers = tokio::spawn(run_worker_until_stopped(configuration, shutdown));
+
+    // Act - Give the workers a few error poll intervals
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    shutdown_sender.send(true).unwrap();
+
+    // Assert - The mock checks on drop that we did not keep calling it
+    tokio::time::timeout(Duration::from_secs(2), workers)
+        .await
+        .expect("The workers did not exit after shutdown was requested.")
+        .unwrap()
+        .unwrap();
+}
+
+async fn expire_idempotency_keys(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - make_interval(secs => $1)",
+        app.idempotency_settings.ttl_seconds as f64 + 1.0
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn an_expired_idempotency_key_is_treated_as_a_new_one() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Submit the same form after its key has expired
+    expire_idempotency_keys(&app).await;
+    let response = app.post_newsletters(&newsletter_form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 2);
+    app.dispatch_all_pending_emails().await;
+    let n_sent: usize = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .map(|r| batch_messages(r).len())
+        .sum();
+    assert_eq!(n_sent, 2, "The newsletter should have been sent twice.");
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_are_cleaned_up() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    publish_newsletter(&app).await;
+    expire_idempotency_keys(&app).await;
+    publish_newsletter(&app).await;
+
+    // Act
+    let n_deleted = delete_expired_keys(&app.db_pool, app.idempotency_settings.ttl())
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_deleted, 2);
+    let n_keys = sqlx::query!(r#"SELECT count(*) as "count!" FROM idempotency"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_keys, 1);
+}
+
+#[tokio::test]
+async fn many_concurrent_submissions_publish_the_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+
+    // Act
+    let (response1, response2, response3) = tokio::join!(
+        app.post_newsletters(&newsletter_form),
+        app.post_newsletters(&newsletter_form),
+        app.post_newsletters(&newsletter_form),
+    );
+
+    // Assert
+    for response in [&response1, &response2, &response3] {
+        assert_is_redirect_to(response, "/admin/newsletters");
+    }
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn a_submission_is_rejected_with_409_while_the_same_key_is_in_flight() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    // Simulate a slow request that has claimed the key but not committed yet
+    let mut in_flight = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        "INSERT INTO idempotency (user_id, idempotency_key, created_at) VALUES ($1, $2, now())",
+        app.test_user.user_id,
+        idempotency_key
+    )
+    .execute(&mut in_flight)
+    .await
+    .unwrap();
+
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.headers().get("Retry-After").is_some());
+    in_flight.rollback().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_submission_is_rejected_with_409_if_the_saved_response_never_shows_up() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    sqlx::query!(
+        "INSERT INTO idempotency (user_id, idempotency_key, created_at) VALUES ($1, $2, now())",
+        app.test_user.user_id,
+        idempotency_key
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response.headers().get("Retry-After").is_some());
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_with_a_different_payload_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let newsletter_form = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    let newsletter_form = serde_json::json!({
+        "title": "Another newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": idempotency_key,
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+}
+
 // #[tokio::test]
 // async fn transient_errors_do_not_cause_duplicate_deliveries_on_retries() {
 //     // Arrange
@@ -0,0 +1,322 @@
+//! tests/api/scheduled_newsletters.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, make_pending_deliveries_due,
+    publish_newsletter, spawn_app, when_sending_a_batch, PostmarkBatchResponder, TestApp,
+};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Schedules an issue `delay` from now and returns its id.
+async fn schedule_newsletter(app: &TestApp, delay: Duration) -> Uuid {
+    let newsletter_form = serde_json::json!({
+        "title": "Scheduled title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": (Utc::now() + delay).to_rfc3339(),
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = 'Scheduled title'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+async fn queued_execute_after(app: &TestApp) -> Vec<DateTime<Utc>> {
+    sqlx::query!("SELECT execute_after FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.execute_after)
+        .collect()
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_their_time() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Schedule
+    schedule_newsletter(&app, Duration::hours(1)).await;
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled for"));
+
+    // Act - Part 2 - Run the workers
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let execute_after = queued_execute_after(&app).await;
+    assert_eq!(execute_after.len(), 1);
+    assert!(execute_after[0] > Utc::now() + Duration::minutes(59));
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_they_are_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    schedule_newsletter(&app, Duration::hours(1)).await;
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    make_pending_deliveries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert!(queued_execute_after(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn scheduling_an_issue_in_the_past_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Scheduled title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p> Newsletter body as HTML </p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "scheduled_for": (Utc::now() - Duration::hours(1)).to_rfc3339(),
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(queued_execute_after(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn retrying_a_scheduled_issue_after_its_time_returns_the_saved_response() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let newsletter_form = serde_json::json!({
+        "title": "Scheduled title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p> Newsletter body as HTML </p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": (Utc::now() + Duration::seconds(1)).to_rfc3339(),
+    });
+    let response = app.post_newsletters(&newsletter_form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let response = app.post_newsletters(&newsletter_form).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(queued_execute_after(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn an_empty_schedule_sends_the_issue_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p> Newsletter body as HTML </p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "scheduled_for": "",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let execute_after = queued_execute_after(&app).await;
+    assert_eq!(execute_after.len(), 1);
+    assert!(execute_after[0] <= Utc::now());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_scheduled_issues() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_scheduled_newsletters().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn only_upcoming_issues_are_listed_as_scheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+
+    // Act
+    let html_page = app.get_scheduled_newsletters_html().await;
+
+    // Assert
+    assert!(html_page.contains("1 scheduled issues."));
+    assert!(html_page.contains(&issue_id.to_string()));
+    assert!(html_page.contains("Scheduled title"));
+    assert!(!html_page.contains("Newsletter title"));
+}
+
+#[tokio::test]
+async fn rescheduling_an_issue_moves_its_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+
+    // Act
+    let response = app
+        .post_reschedule_newsletter(&serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "scheduled_for": "2099-01-01T09:30",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been rescheduled"));
+    assert!(html_page.contains("2099-01-01T09:30:00+00:00"));
+    let expected: DateTime<Utc> = "2099-01-01T09:30:00Z".parse().unwrap();
+    assert_eq!(queued_execute_after(&app).await, vec![expected]);
+}
+
+#[tokio::test]
+async fn an_issue_cannot_be_rescheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+    let before = queued_execute_after(&app).await;
+
+    // Act
+    app.post_reschedule_newsletter(&serde_json::json!({
+        "newsletter_issue_id": issue_id,
+        "scheduled_for": "2000-01-01T09:30",
+    }))
+    .await;
+
+    // Assert
+    let html_page = app.get_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The scheduled time must be in the future."));
+    assert_eq!(queued_execute_after(&app).await, before);
+}
+
+#[tokio::test]
+async fn invalid_schedules_are_escaped_in_the_error_message() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+
+    // Act
+    app.post_reschedule_newsletter(&serde_json::json!({
+        "newsletter_issue_id": issue_id,
+        "scheduled_for": "<script>alert(1)</script>",
+    }))
+    .await;
+
+    // Assert
+    let html_page = app.get_scheduled_newsletters_html().await;
+    assert!(
+        html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt; is not a valid date and time.")
+    );
+    assert!(!html_page.contains("<script>"));
+}
+
+#[tokio::test]
+async fn cancelling_an_issue_drops_its_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_cancel_scheduled_newsletter(&serde_json::json!({
+            "newsletter_issue_id": issue_id,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been cancelled."));
+    assert!(html_page.contains("0 scheduled issues."));
+    assert!(queued_execute_after(&app).await.is_empty());
+    let status = sqlx::query!("SELECT status FROM newsletter_issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "cancelled");
+    make_pending_deliveries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_issue_that_went_out_cannot_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app).await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act
+    app.post_cancel_scheduled_newsletter(&serde_json::json!({
+        "newsletter_issue_id": issue_id,
+    }))
+    .await;
+
+    // Assert
+    let html_page = app.get_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue is no longer scheduled"));
+    assert_eq!(queued_execute_after(&app).await.len(), 1);
+}
@@ -0,0 +1,220 @@
+//! tests/api/subscriber_tags.rs
+
+use crate::helpers::{
+    assert_is_redirect_to, batch_messages, create_confirmed_subscriber, spawn_app,
+    when_sending_a_batch, PostmarkBatchResponder, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect()
+}
+
+async fn tag(app: &TestApp, email: &str, tag: &str) {
+    let response = app
+        .post_tags(&serde_json::json!({ "email": email, "tag": tag }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/tags");
+}
+
+/// Publishes an issue with `audience_filter` and returns the recipients.
+async fn publish_with_filter(app: &TestApp, audience_filter: &str) -> Vec<String> {
+    when_sending_a_batch()
+        .respond_with(PostmarkBatchResponder::accepting_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p> Newsletter body as HTML </p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "audience_filter": audience_filter,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    batch_messages(&email_request)
+        .into_iter()
+        .map(|m| m["To"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_tag_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_tags(&serde_json::json!({ "email": "ursula_le_guin@gmail.com", "tag": "beta" }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_admin_can_tag_and_untag_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_emails(&app).await.pop().unwrap();
+
+    // Act - Part 1 - Tag
+    tag(&app, &email, "Beta").await;
+
+    // Assert - Part 1
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("The subscriber has been tagged."));
+    assert!(html_page.contains("<td>beta</td>"));
+
+    // Act - Part 2 - Untag
+    let response = app
+        .post_remove_tags(&serde_json::json!({ "email": email, "tag": "beta" }))
+        .await;
+
+    // Assert - Part 2
+    assert_is_redirect_to(&response, "/admin/subscribers/tags");
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("The tag has been removed."));
+    assert!(!html_page.contains("<td>beta</td>"));
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_or_with_an_invalid_tag_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_emails(&app).await.pop().unwrap();
+
+    // Act - Part 1 - Unknown subscriber
+    tag(&app, "ursula_le_guin@gmail.com", "beta").await;
+
+    // Assert - Part 1
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("There is no subscriber with this email."));
+
+    // Act - Part 2 - Invalid tag
+    tag(&app, &email, "beta testers").await;
+
+    // Assert - Part 2
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("is not a valid tag"));
+    let n_tags = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriber_tags"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tags, 0);
+}
+
+#[tokio::test]
+async fn issues_filtered_by_tag_are_only_delivered_to_tagged_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let emails = subscriber_emails(&app).await;
+    tag(&app, &emails[0], "beta").await;
+
+    // Act
+    let recipients = publish_with_filter(&app, "tag:beta").await;
+
+    // Assert
+    assert_eq!(recipients, vec![emails[0].clone()]);
+}
+
+#[tokio::test]
+async fn issues_can_target_recent_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let emails = subscriber_emails(&app).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - interval '60 days' WHERE email = $1",
+        emails[0]
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let recipients = publish_with_filter(&app, "subscribed_within:30d").await;
+
+    // Assert
+    assert_eq!(recipients, vec![emails[1].clone()]);
+}
+
+#[tokio::test]
+async fn filters_combine_with_boolean_operators() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let emails = subscriber_emails(&app).await;
+    tag(&app, &emails[0], "beta").await;
+    tag(&app, &emails[1], "beta").await;
+    tag(&app, &emails[1], "staff").await;
+
+    // Act
+    let recipients = publish_with_filter(&app, "tag:beta and not tag:staff").await;
+
+    // Assert
+    assert_eq!(recipients, vec![emails[0].clone()]);
+}
+
+#[tokio::test]
+async fn issues_with_an_invalid_audience_filter_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p> Newsletter body as HTML </p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "audience_filter": "tag:beta and (",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
@@ -1,6 +1,6 @@
 //! tests/api/subscriptions_confirm.rs
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, TestApp};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -19,7 +19,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request).await;
+    let confirmation_links = app.get_confirmation_links(email_request).await;
 
     // Act
     reqwest::get(confirmation_links.html)
@@ -54,7 +54,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     app.post_subscriptions(body.into()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request).await;
+    let confirmation_links = app.get_confirmation_links(email_request).await;
     // Act
     let response = reqwest::get(confirmation_links.html).await.unwrap();
 
@@ -75,3 +75,260 @@ async fn confirmations_without_token_are_rejected_with_a_400() {
     // Assert
     assert_eq!(response.status().as_u16(), 400);
 }
+
+async fn subscribe_and_get_confirmation_link(app: &TestApp) -> reqwest::Url {
+    let body = "name=le%20guin&email=ursula_le_guin%40test.com";
+    app.post_subscriptions(body.into()).await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let email_request = email_requests.last().unwrap();
+    app.get_confirmation_links(email_request).await.html
+}
+
+fn subscription_token(confirmation_link: &reqwest::Url) -> String {
+    confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn subscription_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .status
+}
+
+#[tokio::test]
+async fn a_used_confirmation_link_cannot_confirm_the_subscriber_again() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("expired or has already been used"));
+    assert!(html_page.contains(r#"action="/subscriptions/confirm/resend""#));
+    assert_eq!(subscription_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn following_a_confirmation_link_twice_shows_the_success_page_both_times() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+
+    for _ in 0..2 {
+        // Act
+        let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.contains("Thank you, your subscription is confirmed!"));
+    }
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmation_fails_if_there_is_a_fatal_database_error() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscriptions DROP COLUMN status;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_does_not_confirm_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(subscription_status(&app).await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn resending_for_an_expired_token_sends_a_working_confirmation_link() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let expired_link = subscribe_and_get_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Ask for a new email
+    let response = app
+        .post_resend_confirmation(&subscription_token(&expired_link))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("A new confirmation email is on its way."));
+
+    // Act - Part 2 - Follow the new link
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let new_link = app.get_confirmation_links(email_request).await.html;
+    assert_ne!(new_link, expired_link);
+    let response = reqwest::get(new_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn resending_for_a_confirmed_subscriber_sends_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_resend_confirmation(&subscription_token(&confirmation_link))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your subscription is already confirmed."));
+}
+
+#[tokio::test]
+async fn an_unused_link_cannot_confirm_an_address_that_has_left_since() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+
+    for status in ["unsubscribed", "bounced", "complained"] {
+        sqlx::query!("UPDATE subscriptions SET status = $1", status)
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+
+        // Act
+        let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 410);
+        assert_eq!(subscription_status(&app).await, status);
+    }
+}
+
+#[tokio::test]
+async fn an_older_link_stops_working_once_a_fresh_one_is_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let first_link = subscribe_and_get_confirmation_link(&app).await;
+    let second_link = subscribe_and_get_confirmation_link(&app).await;
+
+    // Act
+    let response = reqwest::get(first_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(subscription_status(&app).await, "pending_confirmation");
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
@@ -0,0 +1,237 @@
+//! tests/api/webhooks.rs
+
+use crate::helpers::{create_confirmed_subscriber, publish_newsletter, spawn_app, TestApp};
+use secrecy::ExposeSecret;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::Environment;
+use zero2prod::startup::Application;
+
+async fn subscriber(app: &TestApp) -> (String, String) {
+    let row = sqlx::query!("SELECT email, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (row.email, row.status)
+}
+
+fn bounce(email: &str, bounce_type: &str, inactive: bool) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": bounce_type,
+        "Email": email,
+        "Inactive": inactive,
+        "Description": "The server was unable to deliver your message.",
+    })
+}
+
+#[tokio::test]
+async fn webhooks_without_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .json(&bounce("ursula@example.com", "HardBounce", true))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="webhooks""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn webhooks_with_a_wrong_password_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .basic_auth(&app.webhook_settings.username, Some("wrong-password"))
+        .json(&bounce("ursula@example.com", "HardBounce", true))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_hard_bounce_marks_the_subscriber_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&bounce(&email, "HardBounce", true))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber(&app).await.1, "bounced");
+}
+
+#[tokio::test]
+async fn a_soft_bounce_leaves_the_subscriber_alone() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&bounce(&email, "SoftBounce", false))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber(&app).await.1, "confirmed");
+}
+
+#[tokio::test]
+async fn a_spam_complaint_marks_the_subscriber_as_complained() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Type": "SpamComplaint",
+            "Email": email,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber(&app).await.1, "complained");
+}
+
+#[tokio::test]
+async fn other_record_types_are_acknowledged_and_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Delivery",
+            "Recipient": email,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber(&app).await.1, "confirmed");
+}
+
+#[tokio::test]
+async fn complained_subscribers_do_not_receive_newsletters_or_confirmation_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let (email, _) = subscriber(&app).await;
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": email,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish an issue
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act - Part 2 - Try to subscribe again
+    let body =
+        serde_urlencoded::to_string([("name", "le guin"), ("email", email.as_str())]).unwrap();
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber(&app).await.1, "complained");
+    // Mock verifies on Drop that no email has been sent
+}
+
+#[tokio::test]
+async fn unauthenticated_webhooks_are_rejected_before_the_payload_is_parsed() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .header("Content-Type", "application/json")
+        .body("not json")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn authenticated_webhooks_with_an_invalid_payload_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .basic_auth(
+            &app.webhook_settings.username,
+            Some(app.webhook_settings.password.expose_secret()),
+        )
+        .header("Content-Type", "application/json")
+        .body("not json")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_development_webhook_password_is_refused_in_production() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration.environment = Environment::Production;
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    match outcome {
+        Ok(_) => panic!("The application started with the development webhook password."),
+        Err(e) => assert!(e.to_string().contains("APP_WEBHOOKS__PASSWORD")),
+    }
+}